@@ -0,0 +1,61 @@
+#import bevy_pbr::mesh_view_bindings
+#import bevy_pbr::mesh_bindings
+#import bevy_pbr::pbr_types
+#import bevy_pbr::utils
+#import bevy_pbr::clustered_forward
+#import bevy_pbr::lighting
+#import bevy_pbr::shadows
+#import bevy_pbr::pbr_functions
+
+struct ClippedPropMaterial {
+    base_color: vec4<f32>,
+    clip_plane: vec4<f32>,
+    perceptual_roughness: f32,
+    metallic: f32,
+    reflectance: f32,
+};
+
+@group(1) @binding(0)
+var<uniform> material: ClippedPropMaterial;
+@group(1) @binding(1)
+var base_color_texture: texture_2d<f32>;
+@group(1) @binding(2)
+var base_color_sampler: sampler;
+
+struct FragmentInput {
+    @builtin(front_facing) is_front: bool,
+    @builtin(position) frag_coord: vec4<f32>,
+    #import bevy_pbr::mesh_vertex_output
+};
+
+@fragment
+fn fragment(in: FragmentInput) -> @location(0) vec4<f32> {
+    // Only keep the part of the prop in front of the portal.
+    if (dot(material.clip_plane, vec4<f32>(in.world_position.xyz, 1.0)) < 0.0) {
+        discard;
+    }
+
+    var output_color: vec4<f32> = material.base_color;
+#ifdef VERTEX_UVS
+    output_color = output_color * textureSample(base_color_texture, base_color_sampler, in.uv);
+#endif
+
+    var pbr_input: PbrInput = pbr_input_new();
+    pbr_input.material.base_color = output_color;
+    pbr_input.material.perceptual_roughness = material.perceptual_roughness;
+    pbr_input.material.metallic = material.metallic;
+    pbr_input.material.reflectance = material.reflectance;
+    pbr_input.frag_coord = in.frag_coord;
+    pbr_input.world_position = in.world_position;
+    // The inside of the props is lit as if it was facing the camera.
+    pbr_input.world_normal = prepare_world_normal(in.world_normal, true, in.is_front);
+    pbr_input.is_orthographic = view.projection[3].w == 1.0;
+    pbr_input.N = normalize(pbr_input.world_normal);
+    pbr_input.V = calculate_view(in.world_position, pbr_input.is_orthographic);
+    output_color = pbr(pbr_input);
+
+#ifdef TONEMAP_IN_SHADER
+    output_color = tone_mapping(output_color);
+#endif
+    return output_color;
+}
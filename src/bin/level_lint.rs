@@ -0,0 +1,90 @@
+//! Headless linter for the level glTF files.
+//!
+//! Usage: `level_lint [PATH]...`, where every path is either a `.glb`/`.gltf` file, or a directory
+//! searched recursively for them. Without arguments, the `assets/levels` directory is checked.
+//!
+//! Every problem found is printed, and the exit code is nonzero if there was any.
+
+use std::{
+    fs,
+    path::{Path, PathBuf},
+    process::ExitCode,
+};
+
+use bevyjam_2::plugins::asset_processor::lint_level;
+
+const DEFAULT_LEVELS_DIR: &str = "assets/levels";
+
+fn main() -> ExitCode {
+    let mut paths = std::env::args_os()
+        .skip(1)
+        .map(PathBuf::from)
+        .collect::<Vec<_>>();
+    if paths.is_empty() {
+        paths.push(PathBuf::from(DEFAULT_LEVELS_DIR));
+    }
+
+    let mut files = Vec::new();
+    let mut failed = false;
+    for path in &paths {
+        if let Err(e) = collect_level_files(path, &mut files) {
+            println!("{}: {}", path.display(), e);
+            failed = true;
+        }
+    }
+
+    for file in files {
+        let gltf = fs::read(&file)
+            .map_err(|e| e.to_string())
+            .and_then(|bytes| gltf::Gltf::from_slice(&bytes).map_err(|e| e.to_string()));
+        match gltf {
+            Ok(gltf) => {
+                let report = lint_level(&gltf, &file.display().to_string());
+                for error in &report.errors {
+                    println!("{}: {}", file.display(), error);
+                }
+                failed |= !report.is_ok();
+            }
+            Err(e) => {
+                println!("{}: can not load glTF file ({})", file.display(), e);
+                failed = true;
+            }
+        }
+    }
+
+    if failed {
+        ExitCode::FAILURE
+    } else {
+        ExitCode::SUCCESS
+    }
+}
+
+/// Collect the glTF files at the given path, recursing into directories.
+fn collect_level_files(path: &Path, files: &mut Vec<PathBuf>) -> std::io::Result<()> {
+    if path.is_dir() {
+        let mut entries = fs::read_dir(path)?
+            .map(|entry| entry.map(|e| e.path()))
+            .collect::<Result<Vec<_>, _>>()?;
+        entries.sort();
+        for entry in entries {
+            if entry.is_dir() || is_gltf_file(&entry) {
+                collect_level_files(&entry, files)?;
+            }
+        }
+    } else if path.is_file() {
+        files.push(path.to_owned());
+    } else {
+        return Err(std::io::Error::new(
+            std::io::ErrorKind::NotFound,
+            "no such file or directory",
+        ));
+    }
+    Ok(())
+}
+
+fn is_gltf_file(path: &Path) -> bool {
+    matches!(
+        path.extension().and_then(|ext| ext.to_str()),
+        Some("glb") | Some("gltf")
+    )
+}
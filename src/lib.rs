@@ -0,0 +1,4 @@
+#![allow(clippy::type_complexity)]
+
+pub mod plugins;
+mod util;
@@ -1,9 +1,6 @@
-#![allow(clippy::type_complexity)]
-
 use bevy::prelude::*;
 
-mod plugins;
-mod util;
+use bevyjam_2::plugins;
 
 fn main() {
     App::new().add_plugin(plugins::game::GamePlugin).run();
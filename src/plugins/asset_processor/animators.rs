@@ -0,0 +1,105 @@
+//! Animation players of the animated level nodes.
+//!
+//! Bevy gives an animation player to the root of every animated hierarchy of a glTF scene. Each
+//! door, floor button and section transition plays its animations on its nearest animated
+//! ancestor, so the animations of separate hierarchies don't interrupt each other.
+
+use bevy::{ecs::system::SystemParam, prelude::*, utils::HashSet};
+
+use crate::plugins::{buttons::FloorButton, doors::Door};
+
+use super::{SceneAnimationPlayer, SectionTransition};
+
+/// Animation player of an animated level node.
+#[derive(Debug, Component)]
+pub struct NodeAnimator(pub Entity);
+
+/// Find the nearest animation player up the hierarchy of the newly spawned animated nodes.
+pub fn find_node_animators(
+    mut commands: Commands,
+    nodes_query: Query<Entity, Or<(Added<Door>, Added<FloorButton>, Added<SectionTransition>)>>,
+    parents_query: Query<&Parent>,
+    players_query: Query<(), With<AnimationPlayer>>,
+) {
+    for node in &nodes_query {
+        let mut ancestor = Some(node);
+        while let Some(entity) = ancestor {
+            if players_query.contains(entity) {
+                commands.entity(node).insert(NodeAnimator(entity));
+                break;
+            }
+            ancestor = parents_query.get(entity).ok().map(|parent| parent.get());
+        }
+    }
+}
+
+/// Animation players of the level nodes.
+#[derive(SystemParam)]
+pub struct NodeAnimators<'w, 's> {
+    node_animators_query: Query<'w, 's, &'static NodeAnimator>,
+    players_query: Query<
+        'w,
+        's,
+        (
+            &'static mut AnimationPlayer,
+            Option<&'static SceneAnimationPlayer>,
+            Entity,
+        ),
+    >,
+}
+
+impl<'w, 's> NodeAnimators<'w, 's> {
+    /// Play an animation on the animation player of a level node, or on the animation player of
+    /// the scene if the node has none. Return whether an animation player was found.
+    pub fn play(&mut self, node: Entity, animation: Handle<AnimationClip>) -> bool {
+        match self.animator(node) {
+            Some(animator) => self.play_on(animator, animation),
+            None => {
+                warn!("No animation player for node {:?}", node);
+                false
+            }
+        }
+    }
+
+    /// Play an animation on the animation players of several level nodes, once per animation
+    /// player. Return whether an animation player was found.
+    pub fn play_all(&mut self, nodes: &[Entity], animation: Handle<AnimationClip>) -> bool {
+        let mut animators = HashSet::new();
+        for node in nodes {
+            match self.animator(*node) {
+                Some(animator) => {
+                    animators.insert(animator);
+                }
+                None => warn!("No animation player for node {:?}", node),
+            }
+        }
+        let mut played = false;
+        for animator in animators {
+            played |= self.play_on(animator, animation.clone());
+        }
+        played
+    }
+
+    fn animator(&self, node: Entity) -> Option<Entity> {
+        self.node_animators_query
+            .get(node)
+            .map(|animator| animator.0)
+            .ok()
+            .or_else(|| {
+                self.players_query
+                    .iter()
+                    .find(|(_, scene_animator, _)| scene_animator.is_some())
+                    .map(|(_, _, entity)| entity)
+            })
+    }
+
+    fn play_on(&mut self, animator: Entity, animation: Handle<AnimationClip>) -> bool {
+        match self.players_query.get_mut(animator) {
+            Ok((mut player, _, _)) => {
+                player.play(animation);
+                true
+            }
+            Err(_) => false,
+        }
+    }
+}
@@ -0,0 +1,254 @@
+//! Deserializers for the custom properties authored in Blender on the level nodes, meshes,
+//! materials and lights, exported as glTF extras.
+
+use bevy::{prelude::*, reflect::FromReflect};
+use serde::{de::IntoDeserializer, Deserialize, Deserializer};
+
+use crate::plugins::{doors::DoorSidedness, game::Ability, signals::LogicOp};
+
+use std::str::FromStr;
+
+#[derive(Debug, Deserialize)]
+pub struct LightExtras {
+    #[serde(default)]
+    #[serde(deserialize_with = "bool_from_string")]
+    pub shadows: Option<bool>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct MeshExtras {
+    #[serde(default)]
+    #[serde(deserialize_with = "bool_from_string")]
+    pub visibility: Option<bool>,
+    #[serde(default)]
+    #[serde(deserialize_with = "bool_from_string")]
+    pub grid: Option<bool>,
+    pub shape: Option<ColliderShape>,
+    #[serde(default)]
+    #[serde(deserialize_with = "bool_from_string")]
+    pub portalable: Option<bool>,
+}
+
+#[derive(Debug, Component, Clone, Deserialize, Default, Reflect, FromReflect)]
+#[reflect(Component)]
+#[serde(rename_all = "snake_case")]
+pub enum ColliderShape {
+    #[default]
+    Convex,
+    Concave,
+}
+
+#[derive(Debug, Clone, Deserialize, Default, Reflect, FromReflect)]
+#[serde(rename_all = "snake_case")]
+pub enum ExtrasAlphaMode {
+    #[default]
+    Opaque,
+    Blend,
+}
+
+impl From<ExtrasAlphaMode> for AlphaMode {
+    fn from(alpha: ExtrasAlphaMode) -> Self {
+        match alpha {
+            ExtrasAlphaMode::Opaque => AlphaMode::Opaque,
+            ExtrasAlphaMode::Blend => AlphaMode::Blend,
+        }
+    }
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub struct MaterialExtras {
+    #[serde(default)]
+    pub alpha: Option<ExtrasAlphaMode>,
+    #[serde(default)]
+    #[serde(deserialize_with = "bool_from_string")]
+    pub portalable: Option<bool>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct NodeExtras {
+    #[serde(default)]
+    #[serde(deserialize_with = "u32_from_string")]
+    pub door_trigger: Option<u32>,
+    #[serde(default)]
+    #[serde(deserialize_with = "u32_from_string")]
+    pub door: Option<u32>,
+    /// The side the door swings open towards, `Left` by default.
+    pub sidedness: Option<DoorSidedness>,
+    /// Signal unlocking the door, whose sensors are ignored while the signal is low.
+    pub locked: Option<String>,
+    /// Duration the door stays open before closing on its own, in seconds.
+    #[serde(default)]
+    #[serde(deserialize_with = "f32_from_string")]
+    pub stay_open_for: Option<f32>,
+    #[serde(default)]
+    #[serde(deserialize_with = "u32_from_string")]
+    pub close_door: Option<u32>,
+    #[serde(default)]
+    #[serde(deserialize_with = "u32_from_string")]
+    pub open_door: Option<u32>,
+    #[serde(default)]
+    #[serde(deserialize_with = "u32_from_string")]
+    pub pickup_sensor: Option<u32>,
+    /// Comma separated abilities granted by the pickup sensor, e.g. `portal_a,portal_b,attract`.
+    #[serde(default)]
+    #[serde(deserialize_with = "abilities_from_string")]
+    pub grants: Option<Vec<Ability>>,
+    #[serde(default)]
+    #[serde(deserialize_with = "u32_from_string")]
+    pub pickup: Option<u32>,
+    pub level_transition: Option<String>,
+    /// Level the `level_transition` section is in, if it isn't the current level.
+    pub next_level: Option<String>,
+    pub section_start: Option<String>,
+    pub section_finish: Option<String>,
+    /// Section of a `.prop`, door or logic gate node, which is only reset along with that section,
+    /// or the section a `level_transition` node leaves.
+    pub section: Option<String>,
+    /// Fixed portal, linked to the other node with the same value.
+    #[serde(alias = "portal_link")]
+    pub portal: Option<String>,
+    /// Hex color of the portal while it is closed.
+    #[serde(default)]
+    #[serde(deserialize_with = "color_from_string")]
+    pub color: Option<Color>,
+    /// Whether the portal is open when the level spawns, `true` by default.
+    #[serde(default)]
+    #[serde(deserialize_with = "bool_from_string")]
+    pub active: Option<bool>,
+    /// Volume closing the player portals and destroying the props going through it.
+    #[serde(default)]
+    #[serde(deserialize_with = "bool_from_string")]
+    pub fizzler: Option<bool>,
+    /// Volume making its node the respawn point of the player once the player goes through it.
+    #[serde(default)]
+    #[serde(deserialize_with = "bool_from_string")]
+    pub checkpoint: Option<bool>,
+    /// Volume killing the player, and returning the props entering it to their authored transform.
+    #[serde(default)]
+    #[serde(deserialize_with = "bool_from_string")]
+    pub kill_volume: Option<bool>,
+    /// Laser firing along the node's local Z axis.
+    #[serde(default)]
+    #[serde(deserialize_with = "bool_from_string")]
+    pub laser_emitter: Option<bool>,
+    /// ID of the doors opened while a laser hits the node.
+    #[serde(default)]
+    #[serde(deserialize_with = "u32_from_string")]
+    pub laser_receiver: Option<u32>,
+    /// Prop reflecting the lasers.
+    #[serde(default)]
+    #[serde(deserialize_with = "bool_from_string")]
+    pub laser_redirector: Option<bool>,
+    /// ID of the floor button, used to find its press and release animations.
+    #[serde(default)]
+    #[serde(deserialize_with = "u32_from_string")]
+    pub floor_button: Option<u32>,
+    /// Mass needed to press the floor button, in kilograms. Required on floor button nodes.
+    #[serde(default)]
+    #[serde(deserialize_with = "f32_from_string")]
+    pub mass_threshold: Option<f32>,
+    /// Signal driven by the door trigger, laser receiver, pickup sensor, floor button or logic
+    /// gate of the node.
+    /// A node with no such element is a sensor volume, high while something is inside it.
+    pub signal: Option<String>,
+    /// Logic gate driving the `signal` of the node from its `inputs`.
+    pub logic: Option<LogicOp>,
+    /// Comma separated signals read by the logic gate.
+    #[serde(default)]
+    #[serde(deserialize_with = "list_from_string")]
+    pub inputs: Option<Vec<String>>,
+    /// Duration of a `timer` logic gate, in seconds.
+    #[serde(default)]
+    #[serde(deserialize_with = "f32_from_string")]
+    pub duration: Option<f32>,
+    /// Signal followed by the door, level portal or section transition of the node.
+    pub on_signal: Option<String>,
+}
+
+fn abilities_from_string<'de, D>(deserializer: D) -> Result<Option<Vec<Ability>>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    let s = String::deserialize(deserializer)?;
+    s.split(',')
+        .map(str::trim)
+        .filter(|item| !item.is_empty())
+        .map(|item| Ability::deserialize(IntoDeserializer::<D::Error>::into_deserializer(item)))
+        .collect::<Result<Vec<_>, _>>()
+        .map(Some)
+}
+
+fn bool_from_string<'de, D>(deserializer: D) -> Result<Option<bool>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    let s = String::deserialize(deserializer)?;
+    match bool::from_str(&s) {
+        Ok(val) => Ok(Some(val)),
+        Err(_) => Err(serde::de::Error::invalid_value(
+            serde::de::Unexpected::Str(&s),
+            &"coerces to bool",
+        )),
+    }
+}
+
+fn color_from_string<'de, D>(deserializer: D) -> Result<Option<Color>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    let s = String::deserialize(deserializer)?;
+    match Color::hex(s.trim_start_matches('#')) {
+        Ok(val) => Ok(Some(val)),
+        Err(_) => Err(serde::de::Error::invalid_value(
+            serde::de::Unexpected::Str(&s),
+            &"a hex color",
+        )),
+    }
+}
+
+fn f32_from_string<'de, D>(deserializer: D) -> Result<Option<f32>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    let s = String::deserialize(deserializer)?;
+    match f32::from_str(&s) {
+        Ok(val) => Ok(Some(val)),
+        Err(_) => Err(serde::de::Error::invalid_value(
+            serde::de::Unexpected::Str(&s),
+            &"coerces to f32",
+        )),
+    }
+}
+
+fn list_from_string<'de, D>(deserializer: D) -> Result<Option<Vec<String>>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    let s = String::deserialize(deserializer)?;
+    Ok(Some(
+        s.split(',')
+            .map(str::trim)
+            .filter(|item| !item.is_empty())
+            .map(str::to_owned)
+            .collect(),
+    ))
+}
+
+fn u32_from_string<'de, D>(deserializer: D) -> Result<Option<u32>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    let s = Option::<String>::deserialize(deserializer)?;
+    if s.is_none() {
+        return Ok(None);
+    }
+    let s = s.unwrap();
+    match u32::from_str(&s) {
+        Ok(val) => Ok(Some(val)),
+        Err(_) => Err(serde::de::Error::invalid_value(
+            serde::de::Unexpected::Str(&s),
+            &"coerces to u32",
+        )),
+    }
+}
@@ -6,9 +6,16 @@ use bevy::{
 };
 use bevy_rapier3d::prelude::*;
 
-use crate::plugins::{doors::Door, first_person_controller::FirstPersonController};
+use crate::plugins::{
+    doors::Door,
+    first_person_controller::FirstPersonController,
+    signals::{SignalSink, Signals},
+};
 
-use super::{level_processor::CurrentLevel, SceneAnimationPlayer};
+use super::{
+    level_processor::{CarriedPlayer, CurrentLevel},
+    LevelProcessor, LevelReport, NodeAnimators, ANIMATION_OPEN_DOOR_PREFIX,
+};
 
 #[derive(Debug, TypeUuid)]
 #[uuid = "731c8e90-b2ea-4f05-b7cd-b694101e5a7c"]
@@ -17,18 +24,53 @@ pub struct Level {
     pub(crate) gltf: Handle<Gltf>,
     pub(crate) scene: Handle<Scene>,
     pub(crate) name: String,
+    pub(crate) report: Handle<LevelReport>,
+    /// Sections of this level, which have a start point.
+    pub(crate) sections: Vec<String>,
+    /// Section transitions of this level, used to stream the levels around the player.
+    pub(crate) section_links: Vec<SectionLink>,
 }
 
 impl Level {
-    pub fn new(gltf: Handle<Gltf>, scene: Handle<Scene>, name: String) -> Level {
-        Level { gltf, scene, name }
+    pub fn new(
+        gltf: Handle<Gltf>,
+        scene: Handle<Scene>,
+        name: String,
+        report: Handle<LevelReport>,
+        sections: Vec<String>,
+        section_links: Vec<SectionLink>,
+    ) -> Level {
+        Level {
+            gltf,
+            scene,
+            name,
+            report,
+            sections,
+            section_links,
+        }
     }
+
+    pub fn has_section(&self, section_name: &str) -> bool {
+        self.sections.iter().any(|section| section == section_name)
+    }
+}
+
+/// Edge of the section transition graph, from a section of a level to another section.
+#[derive(Debug, Clone)]
+pub struct SectionLink {
+    /// Section the transition leaves, `None` if it can be taken from every section.
+    pub from_section: Option<NodeSection>,
+    pub to_section: String,
+    /// Level the target section is in, `None` if it is in the same level.
+    pub to_level: Option<String>,
 }
 
 #[derive(Debug, Default, Clone, Component, Reflect, FromReflect)]
 #[reflect(Component)]
 pub struct SectionTransition {
     pub target_level: String,
+    /// Level the target section is in, `None` if it is in the current level.
+    pub next_level: Option<String>,
     pub close_door: u32,
     pub open_door: u32,
     pub close_animation: Handle<AnimationClip>,
@@ -39,12 +81,24 @@ pub struct SectionTransition {
 pub struct PendingTransition {
     pub source: Entity,
     pub destination: Entity,
+    pub open_door: u32,
     pub open_animation: Handle<AnimationClip>,
     pub timer: Timer,
     pub next_section_name: String,
     pub teleported: bool,
 }
 
+/// Transition to a section of another level, which is spawned once the door is closed.
+#[derive(Debug, Clone, Reflect, Resource)]
+pub struct PendingLevelTransition {
+    pub source: Entity,
+    pub level_name: String,
+    pub next_section_name: String,
+    pub open_door: u32,
+    pub timer: Timer,
+    pub spawn_requested: bool,
+}
+
 #[derive(Debug, Default, Clone, Component, Reflect, FromReflect)]
 #[reflect(Component)]
 pub struct SectionStart {
@@ -57,6 +111,30 @@ pub struct SectionFinish {
     pub section_name: String,
 }
 
+/// Dynamic prop of the level, along with the transform it was authored with.
+#[derive(Debug, Default, Clone, Component, Reflect, FromReflect)]
+#[reflect(Component)]
+pub struct LevelProp {
+    pub authored_transform: Transform,
+}
+
+/// Section a level prop, door or logic gate belongs to. Nodes without a section belong to no section, and are
+/// left alone by the section resets.
+#[derive(Debug, Default, Clone, Component, Reflect, FromReflect)]
+#[reflect(Component)]
+pub struct NodeSection {
+    pub section_name: String,
+}
+
+impl NodeSection {
+    /// Whether the node belongs to the given section.
+    pub fn contains(node_section: Option<&NodeSection>, section_name: &str) -> bool {
+        node_section.map_or(false, |node_section| {
+            node_section.section_name == section_name
+        })
+    }
+}
+
 #[derive(Debug, Clone, Reflect, FromReflect)]
 pub struct Section {
     pub name: String,
@@ -107,45 +185,82 @@ pub fn init_section_table(
     commands.insert_resource(transition_table);
 }
 
+#[allow(clippy::too_many_arguments)]
 pub fn initiate_section_transition(
     mut commands: Commands,
-    mut animator_query: Query<Option<&mut AnimationPlayer>, With<SceneAnimationPlayer>>,
+    mut animators: NodeAnimators,
     mut collisions: EventReader<CollisionEvent>,
-    mut transitions_query: Query<(&mut SectionTransition, Entity), Without<Door>>,
+    mut transitions_query: Query<
+        (&mut SectionTransition, Option<&SignalSink>, Entity),
+        Without<Door>,
+    >,
+    doors_query: Query<(&Door, Entity)>,
+    mut level_manager: ResMut<LevelProcessor>,
     current_level: Res<CurrentLevel>,
     sections: Res<SectionTable>,
+    signals: Res<Signals>,
+    asset_server: Res<AssetServer>,
 ) {
-    if let Ok(Some(mut animator)) = animator_query.get_single_mut() {
-        for collision in collisions.iter() {
-            if let CollisionEvent::Started(collider_a, collider_b, _flags) = collision {
-                let maybe_sensor_entity = transitions_query
-                    .get(*collider_a)
-                    .or_else(|_| transitions_query.get(*collider_b))
-                    .map(|r| r.1);
-                if let Ok(sensor_entity) = maybe_sensor_entity {
-                    let (transition, _sensor_entity) =
-                        transitions_query.get_mut(sensor_entity).unwrap();
-                    info!(
-                        "Sensor for transition to level {} activated",
-                        transition.target_level
-                    );
-                    animator.play(transition.close_animation.clone());
-                    let section = sections
-                        .table
-                        .get(&current_level.current_section())
-                        .unwrap();
-                    let next_section = sections.table.get(&transition.target_level).unwrap();
-                    let end = section.finish_point.unwrap();
-                    let destination = next_section.spawn_point;
-                    commands.insert_resource(PendingTransition {
+    for collision in collisions.iter() {
+        if let CollisionEvent::Started(collider_a, collider_b, _flags) = collision {
+            let maybe_sensor_entity = transitions_query
+                .get(*collider_a)
+                .or_else(|_| transitions_query.get(*collider_b))
+                .map(|r| r.2);
+            if let Ok(sensor_entity) = maybe_sensor_entity {
+                let (transition, sink, _sensor_entity) =
+                    transitions_query.get_mut(sensor_entity).unwrap();
+                if let Some(sink) = sink {
+                    if !signals.is_high(&sink.signal) {
+                        info!(
+                            "Transition to level {} is locked by signal {}",
+                            transition.target_level, sink.signal
+                        );
+                        continue;
+                    }
+                }
+                info!(
+                    "Sensor for transition to level {} activated",
+                    transition.target_level
+                );
+                let close_doors = find_doors(&doors_query, transition.close_door);
+                if close_doors.is_empty() {
+                    animators.play(sensor_entity, transition.close_animation.clone());
+                } else {
+                    animators.play_all(&close_doors, transition.close_animation.clone());
+                }
+                let section = sections
+                    .table
+                    .get(&current_level.current_section())
+                    .unwrap();
+                let end = section.finish_point.unwrap();
+                if let Some(next_level) = &transition.next_level {
+                    // Load the next level while the door closes
+                    if let Err(e) = level_manager.preload_level(next_level, &asset_server) {
+                        error!("Can not transition to level {}: {}", next_level, e);
+                        continue;
+                    }
+                    commands.insert_resource(PendingLevelTransition {
                         source: end,
-                        destination,
-                        open_animation: transition.open_animation.clone(),
-                        timer: Timer::from_seconds(3., TimerMode::Once),
+                        level_name: next_level.clone(),
                         next_section_name: transition.target_level.clone(),
-                        teleported: false,
-                    })
+                        open_door: transition.open_door,
+                        timer: Timer::from_seconds(3., TimerMode::Once),
+                        spawn_requested: false,
+                    });
+                    continue;
                 }
+                let next_section = sections.table.get(&transition.target_level).unwrap();
+                let destination = next_section.spawn_point;
+                commands.insert_resource(PendingTransition {
+                    source: end,
+                    destination,
+                    open_door: transition.open_door,
+                    open_animation: transition.open_animation.clone(),
+                    timer: Timer::from_seconds(3., TimerMode::Once),
+                    next_section_name: transition.target_level.clone(),
+                    teleported: false,
+                })
             }
         }
     }
@@ -155,7 +270,8 @@ pub fn initiate_section_transition(
 pub fn perform_section_transition(
     mut commands: Commands,
     mut player_query: Query<(&mut Transform, Entity), With<FirstPersonController>>,
-    mut animator_query: Query<(&Name, Option<&mut AnimationPlayer>), With<SceneAnimationPlayer>>,
+    mut animators: NodeAnimators,
+    doors_query: Query<(&Door, Entity)>,
     mut current_level: ResMut<CurrentLevel>,
     global_transform_query: Query<&GlobalTransform>,
     transition: Option<ResMut<PendingTransition>>,
@@ -212,11 +328,12 @@ pub fn perform_section_transition(
         transition.timer.tick(time.delta());
         if transition.timer.finished() {
             // Trigger the door open animation
-            for animator in &animator_query {
-                dbg!(animator.0);
+            let open_doors = find_doors(&doors_query, transition.open_door);
+            if open_doors.is_empty() {
+                animators.play(transition.destination, transition.open_animation.clone());
+            } else {
+                animators.play_all(&open_doors, transition.open_animation.clone());
             }
-            let mut animator = animator_query.single_mut().1.unwrap();
-            animator.play(dbg!(transition.open_animation.clone()));
 
             commands.entity(player_entity).insert(RigidBody::Dynamic);
 
@@ -226,3 +343,103 @@ pub fn perform_section_transition(
         }
     }
 }
+
+#[allow(clippy::too_many_arguments)]
+pub fn perform_level_transition(
+    mut commands: Commands,
+    mut player_query: Query<(&Transform, &Velocity, &mut FirstPersonController, Entity)>,
+    mut animators: NodeAnimators,
+    doors_query: Query<(&Door, Entity)>,
+    mut level_manager: ResMut<LevelProcessor>,
+    current_level: Res<CurrentLevel>,
+    levels: Res<Assets<Level>>,
+    gltfs: Res<Assets<Gltf>>,
+    global_transform_query: Query<&GlobalTransform>,
+    transition: Option<ResMut<PendingLevelTransition>>,
+    time: Res<Time>,
+) {
+    if let Some(mut transition) = transition {
+        if transition.spawn_requested {
+            // Back in game in the next level, open its door
+            let level = levels.get(&current_level.get()).unwrap();
+            if level.name != transition.level_name {
+                return;
+            }
+            let animation_name = format!("{}_{}", ANIMATION_OPEN_DOOR_PREFIX, transition.open_door);
+            match gltfs
+                .get(&level.gltf)
+                .and_then(|gltf| gltf.named_animations.get(&animation_name))
+            {
+                Some(animation) => {
+                    let open_doors = find_doors(&doors_query, transition.open_door);
+                    if open_doors.is_empty() {
+                        warn!("Level {} has no door {}", level.name, transition.open_door);
+                    } else {
+                        animators.play_all(&open_doors, animation.clone());
+                    }
+                }
+                None => warn!("Level {} has no animation {}", level.name, animation_name),
+            }
+            commands.remove_resource::<PendingLevelTransition>();
+            return;
+        }
+
+        transition.timer.tick(time.delta());
+        // Wait for the door to close and the next level to be loaded
+        if transition.timer.percent_left() > 0.2
+            || !level_manager.is_level_loaded(&transition.level_name)
+        {
+            return;
+        }
+
+        let (player, velocity, mut controller, player_entity) = player_query.single_mut();
+        // The props of the current level are about to be despawned
+        controller.grabbed_object = None;
+
+        // Keep the player transform and velocity relative to the finish point, to apply them
+        // relative to the start point of the next section.
+        let source = global_transform_query
+            .get(transition.source)
+            .unwrap()
+            .compute_transform();
+        let carried = CarriedPlayer {
+            section: transition.next_section_name.clone(),
+            transform: Transform::from_matrix(
+                source.compute_matrix().inverse() * player.compute_matrix(),
+            ),
+            velocity: Velocity {
+                linvel: source.rotation.inverse() * velocity.linvel,
+                angvel: velocity.angvel,
+            },
+        };
+        commands
+            .entity(player_entity)
+            .insert(RigidBody::KinematicPositionBased);
+
+        match level_manager.carry_player_to_level(
+            &mut commands,
+            &levels,
+            &transition.level_name,
+            carried,
+        ) {
+            Ok(()) => transition.spawn_requested = true,
+            Err(e) => {
+                error!(
+                    "Can not transition to level {}: {}",
+                    transition.level_name, e
+                );
+                commands.entity(player_entity).insert(RigidBody::Dynamic);
+                commands.remove_resource::<PendingLevelTransition>();
+            }
+        }
+    }
+}
+
+/// Return every door with the given ID.
+fn find_doors(doors_query: &Query<(&Door, Entity)>, id: u32) -> Vec<Entity> {
+    doors_query
+        .iter()
+        .filter(|(door, _)| door.id == id)
+        .map(|(_, entity)| entity)
+        .collect()
+}
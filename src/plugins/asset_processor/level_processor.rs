@@ -1,3 +1,5 @@
+use std::collections::VecDeque;
+
 use bevy::{
     gltf::{Gltf, GltfExtras},
     prelude::*,
@@ -7,154 +9,103 @@ use bevy::{
 };
 use bevy_rapier3d::prelude::*;
 use iyes_loopless::prelude::*;
-use serde::{Deserialize, Deserializer};
-
-use std::str::FromStr;
 
 use crate::plugins::{
-    doors::{Door, DoorSensor},
+    buttons::FloorButton,
+    checkpoints::{Checkpoint, KillVolume},
+    doors::{Door, DoorAnimations, DoorSensor, DoorSidedness},
     first_person_controller::*,
     game::*,
+    lasers::{LaserEmitter, LaserReceiver, LaserRedirector},
     physics::*,
-    portal::PortalTeleport,
+    portal::{
+        Fizzler, LevelPortal, NonPortalable, PortalBundle, PortalEnd, PortalResources,
+        PortalTeleport,
+    },
     render::RenderResources,
+    signals::{LogicGate, SignalSensor, SignalSink, SignalSource},
 };
 
-use super::{level::*, SectionStart, SpawnState};
-
-pub const LEVEL_LIST: &[&str] = &["Level1"];
+use super::{
+    extras::*, level::*, lint::check_portal_links, manifest::*, validation::*, SectionStart,
+    SpawnState,
+};
 
 pub const LEVEL_STATIC_GEOMETRY_SUFFIX: &str = ".fixed";
 pub const LEVEL_GROUND_GEOMETRY_SUFFIX: &str = ".ground";
 pub const LEVEL_DYNAMIC_GEOMETRY_SUFFIX: &str = ".prop";
 pub const ANIMATION_OPEN_DOOR_PREFIX: &str = "OpenDoor";
 pub const ANIMATION_CLOSE_DOOR_PREFIX: &str = "CloseDoor";
+pub const ANIMATION_PRESS_BUTTON_PREFIX: &str = "PressButton";
+pub const ANIMATION_RELEASE_BUTTON_PREFIX: &str = "ReleaseButton";
 
 #[derive(Debug, Component, Default, Reflect, FromReflect)]
 #[reflect(Component)]
 pub struct SceneAnimationPlayer;
 
-#[derive(Debug, Deserialize)]
-struct LightExtras {
-    #[serde(deserialize_with = "bool_from_string")]
-    pub shadows: Option<bool>,
+#[derive(Debug, Default, Reflect, FromReflect, Resource)]
+pub struct CurrentLevel {
+    level: Handle<Level>,
+    pub(crate) section: String,
 }
 
-#[derive(Debug, Deserialize)]
-pub(crate) struct MeshExtras {
-    #[serde(default)]
-    #[serde(deserialize_with = "bool_from_string")]
-    visibility: Option<bool>,
-    #[serde(default)]
-    #[serde(deserialize_with = "bool_from_string")]
-    grid: Option<bool>,
-    shape: Option<ColliderShape>,
-}
+#[allow(dead_code)]
+impl CurrentLevel {
+    pub fn get(&self) -> Handle<Level> {
+        self.level.clone()
+    }
 
-#[derive(Debug, Component, Clone, Deserialize, Default, Reflect, FromReflect)]
-#[reflect(Component)]
-#[serde(rename_all = "snake_case")]
-pub enum ColliderShape {
-    #[default]
-    Convex,
-    Concave,
+    pub fn current_section(&self) -> String {
+        self.section.clone()
+    }
 }
 
-#[derive(Debug, Clone, Deserialize, Default, Reflect, FromReflect)]
-enum ExtrasAlphaMode {
-    #[default]
-    Opaque,
-    Blend,
+/// Streaming policy of the levels around the current section.
+#[derive(Debug, Resource)]
+pub struct LevelStreaming {
+    /// Levels whose sections are all more than this number of section transitions away from the
+    /// current section get unloaded.
+    pub unload_distance: usize,
 }
 
-impl From<ExtrasAlphaMode> for AlphaMode {
-    fn from(alpha: ExtrasAlphaMode) -> Self {
-        match alpha {
-            ExtrasAlphaMode::Opaque => AlphaMode::Opaque,
-            ExtrasAlphaMode::Blend => AlphaMode::Blend,
-        }
+impl Default for LevelStreaming {
+    fn default() -> Self {
+        LevelStreaming { unload_distance: 1 }
     }
 }
 
-#[derive(Debug, Deserialize)]
-#[serde(rename_all = "snake_case")]
-pub(crate) struct MaterialExtras {
-    #[serde(default)]
-    alpha: Option<ExtrasAlphaMode>,
+/// Progress of the levels loading in the background, e.g. for a loading screen.
+#[derive(Debug, Default, Resource)]
+pub struct LevelLoadProgress {
+    /// Number of levels requested since nothing was loading.
+    pub requested: usize,
+    /// Number of those levels which are loaded.
+    pub loaded: usize,
+    /// Levels which failed to load.
+    pub failed: Vec<String>,
 }
 
-#[derive(Debug, Deserialize)]
-pub struct NodeExtras {
-    #[serde(default)]
-    #[serde(deserialize_with = "u32_from_string")]
-    door_trigger: Option<u32>,
-    #[serde(default)]
-    #[serde(deserialize_with = "u32_from_string")]
-    door: Option<u32>,
-    #[serde(default)]
-    #[serde(deserialize_with = "u32_from_string")]
-    close_door: Option<u32>,
-    #[serde(default)]
-    #[serde(deserialize_with = "u32_from_string")]
-    open_door: Option<u32>,
-    #[serde(default)]
-    #[serde(deserialize_with = "u32_from_string")]
-    pickup_sensor: Option<u32>,
-    #[serde(default)]
-    #[serde(deserialize_with = "u32_from_string")]
-    pickup: Option<u32>,
-    level_transition: Option<String>,
-    section_start: Option<String>,
-    section_finish: Option<String>,
-}
-
-fn bool_from_string<'de, D>(deserializer: D) -> Result<Option<bool>, D::Error>
-where
-    D: Deserializer<'de>,
-{
-    let s = String::deserialize(deserializer)?;
-    match bool::from_str(&s) {
-        Ok(val) => Ok(Some(val)),
-        Err(_) => Err(serde::de::Error::invalid_value(
-            serde::de::Unexpected::Str(&s),
-            &"coerces to bool",
-        )),
+impl LevelLoadProgress {
+    pub fn is_done(&self) -> bool {
+        self.loaded >= self.requested
     }
-}
 
-fn u32_from_string<'de, D>(deserializer: D) -> Result<Option<u32>, D::Error>
-where
-    D: Deserializer<'de>,
-{
-    let s = Option::<String>::deserialize(deserializer)?;
-    if s.is_none() {
-        return Ok(None);
-    }
-    let s = s.unwrap();
-    match u32::from_str(&s) {
-        Ok(val) => Ok(Some(val)),
-        Err(_) => Err(serde::de::Error::invalid_value(
-            serde::de::Unexpected::Str(&s),
-            &"coerces to u32",
-        )),
+    /// Fraction of the requested levels which are loaded, between 0 and 1.
+    pub fn fraction(&self) -> f32 {
+        if self.requested == 0 {
+            1.
+        } else {
+            self.loaded as f32 / self.requested as f32
+        }
     }
 }
 
-#[derive(Debug, Default, Reflect, FromReflect, Resource)]
-pub struct CurrentLevel {
-    level: Handle<Level>,
-    pub(crate) section: String,
-}
-
-#[allow(dead_code)]
-impl CurrentLevel {
-    pub fn get(&self) -> Handle<Level> {
-        self.level.clone()
-    }
-
-    pub fn current_section(&self) -> String {
-        self.section.clone()
-    }
+/// State of the player carried over to another level, relative to the section start point.
+#[derive(Debug, Clone)]
+pub(crate) struct CarriedPlayer {
+    pub section: String,
+    pub transform: Transform,
+    pub velocity: Velocity,
 }
 
 #[derive(Debug, Resource)]
@@ -167,6 +118,11 @@ pub struct LevelProcessor {
     loading_levels: HashMap<String, Handle<Gltf>>,
     hot_reloaded: HashSet<Handle<Gltf>>,
     spawn_state: SpawnState,
+    manifest: Handle<LevelManifest>,
+    manifest_levels: Vec<LevelManifestEntry>,
+    carried_player: Option<CarriedPlayer>,
+    requested_count: usize,
+    loaded_count: usize,
 }
 
 impl LevelProcessor {
@@ -180,6 +136,11 @@ impl LevelProcessor {
             loading_levels: HashMap::new(),
             hot_reloaded: HashSet::new(),
             spawn_state: SpawnState::Idle,
+            manifest: Handle::default(),
+            manifest_levels: Vec::new(),
+            carried_player: None,
+            requested_count: 0,
+            loaded_count: 0,
         }
     }
 
@@ -187,6 +148,31 @@ impl LevelProcessor {
         self.current_level.clone()
     }
 
+    /// Load the level manifest. The levels it lists are loaded once it is available.
+    pub fn load_manifest(&mut self, manifest_path: &str, asset_server: &Res<AssetServer>) {
+        self.manifest = asset_server.load(manifest_path);
+    }
+
+    /// Name of the first level of the manifest, if it is loaded.
+    pub fn first_level(&self) -> Option<&str> {
+        self.manifest_levels
+            .first()
+            .map(|level| level.name.as_str())
+    }
+
+    /// Name of the section the player spawns in when instantiating the given level.
+    pub fn start_section(&self, level_name: &str) -> Option<&str> {
+        self.manifest_levels
+            .iter()
+            .find(|level| level.name == level_name)
+            .map(|level| level.start_section.as_str())
+    }
+
+    fn current_start_section(&self, levels: &Assets<Level>) -> Option<String> {
+        let level = levels.get(self.current_level.as_ref()?)?;
+        self.start_section(&level.name).map(str::to_owned)
+    }
+
     /// Load a level into memory from a GLTF file.
     pub fn load_level(
         &mut self,
@@ -195,9 +181,107 @@ impl LevelProcessor {
         asset_server: &Res<AssetServer>,
     ) {
         let level_handle = asset_server.load(gltf_level);
+        if self.loading_levels.is_empty() {
+            self.requested_count = 0;
+            self.loaded_count = 0;
+        }
+        self.requested_count += 1;
         self.loading_levels.insert(level_name, level_handle);
     }
 
+    /// Drop a loaded level, freeing its assets and despawning the scenes spawned from it. The
+    /// current level can not be unloaded.
+    fn unload_level(
+        &mut self,
+        commands: &mut Commands,
+        level_name: &str,
+        levels: &mut Assets<Level>,
+        scene_roots_query: &Query<(&Handle<Scene>, Entity)>,
+    ) {
+        if let Some(handle) = self.loaded_levels.get(level_name) {
+            if self.current_level.as_ref() == Some(handle) {
+                return;
+            }
+        }
+        if let Some(handle) = self.loaded_levels.remove(level_name) {
+            info!("Unloading level {}", level_name);
+            if let Some(level) = levels.get(&handle) {
+                for (scene, root) in scene_roots_query {
+                    if *scene == level.scene {
+                        commands.entity(root).despawn_recursive();
+                    }
+                }
+            }
+            self.loaded_levels_gltfs
+                .retain(|_gltf, level| *level != handle);
+            levels.remove(handle);
+        }
+    }
+
+    /// Load a level listed in the manifest, unless it is already loaded or loading.
+    pub fn preload_level(
+        &mut self,
+        level_name: &str,
+        asset_server: &Res<AssetServer>,
+    ) -> Result<(), String> {
+        if self.is_level_loaded(level_name) || self.loading_levels.contains_key(level_name) {
+            return Ok(());
+        }
+        let path = self
+            .manifest_levels
+            .iter()
+            .find(|level| level.name == level_name)
+            .map(|level| level.path.clone())
+            .ok_or_else(|| format!("Level {} is not listed in the manifest", level_name))?;
+        self.load_level(&path, level_name.to_owned(), asset_server);
+        Ok(())
+    }
+
+    pub fn is_level_loaded(&self, level_name: &str) -> bool {
+        self.loaded_levels.contains_key(level_name)
+    }
+
+    /// Whether the given level is loaded and has the given section.
+    pub fn level_has_section(
+        &self,
+        level_name: &str,
+        section_name: &str,
+        levels: &Assets<Level>,
+    ) -> bool {
+        self.loaded_levels
+            .get(level_name)
+            .and_then(|handle| levels.get(handle))
+            .map_or(false, |level| level.has_section(section_name))
+    }
+
+    /// Swap the current level for another one, keeping the player entity and its momentum. The
+    /// player starts at the level start section if the level has no section it can be carried to.
+    pub(crate) fn carry_player_to_level(
+        &mut self,
+        commands: &mut Commands,
+        levels: &Assets<Level>,
+        level_name: &str,
+        mut player: CarriedPlayer,
+    ) -> Result<(), String> {
+        if !self.level_has_section(level_name, &player.section, levels) {
+            let start_section = self
+                .start_section(level_name)
+                .ok_or_else(|| format!("Level {} is not listed in the manifest", level_name))?;
+            error!(
+                "Level {} has no section {}, starting at section {}",
+                level_name, player.section, start_section
+            );
+            player = CarriedPlayer {
+                section: start_section.to_owned(),
+                transform: Transform::IDENTITY,
+                velocity: Velocity::default(),
+            };
+        }
+        self.instantiate_level(commands, level_name)?;
+        self.carried_player = Some(player);
+        Ok(())
+    }
+
     pub fn instantiate_level(
         &mut self,
         commands: &mut Commands,
@@ -239,10 +323,13 @@ impl LevelProcessor {
     }
 
     /// Process point lights in the scene to adjust the shadows setting.
-    pub(crate) fn preprocess_point_lights(scene: &mut Scene) {
-        let mut query = scene.world.query::<(&mut PointLight, &GltfExtras)>();
-        for (mut light, extras) in query.iter_mut(&mut scene.world) {
-            if let Ok(tags) = serde_json::from_str::<LightExtras>(&extras.value) {
+    pub(crate) fn preprocess_point_lights(scene: &mut Scene, report: &mut LevelReport) {
+        let mut query = scene
+            .world
+            .query::<(&mut PointLight, &GltfExtras, Option<&Name>)>();
+        for (mut light, extras, name) in query.iter_mut(&mut scene.world) {
+            let name = name.map(|n| n.as_str()).unwrap_or_default();
+            if let Some(tags) = parse_extras::<LightExtras>(name, &extras.value, report) {
                 if let Some(true) = tags.shadows {
                     light.shadows_enabled = true;
                 }
@@ -250,36 +337,58 @@ impl LevelProcessor {
         }
     }
 
-    /// Modify the alpha blending attribute of standard materials.
+    /// Modify the alpha blending attribute of standard materials, and tag the meshes whose
+    /// material refuses portals.
     pub(crate) fn preprocess_materials(
         scene: &mut Scene,
         materials: &mut ResMut<Assets<StandardMaterial>>,
+        report: &mut LevelReport,
     ) {
-        let mut query = scene
-            .world
-            .query::<(&Handle<StandardMaterial>, &GltfExtras)>();
-        for (material_handle, extras) in query.iter(&scene.world) {
-            if let Ok(tags) = serde_json::from_str::<MaterialExtras>(&extras.value) {
+        let mut query = scene.world.query::<(
+            &Handle<StandardMaterial>,
+            &GltfExtras,
+            Option<&Name>,
+            Entity,
+        )>();
+        let mut non_portalable = Vec::new();
+        for (material_handle, extras, name, id) in query.iter(&scene.world) {
+            let name = name.map(|n| n.as_str()).unwrap_or_default();
+            if let Some(tags) = parse_extras::<MaterialExtras>(name, &extras.value, report) {
                 if let Some(alpha) = tags.alpha {
                     let material = materials.get_mut(material_handle).unwrap();
                     material.alpha_mode = alpha.into();
                 }
+                if let Some(false) = tags.portalable {
+                    non_portalable.push(id);
+                }
             }
         }
+
+        for id in non_portalable {
+            scene.world.entity_mut(id).insert(NonPortalable);
+        }
     }
 
     /// Modify the visibility components of meshes.
-    pub(crate) fn preprocess_meshes(scene: &mut Scene, grids: &Res<RenderResources>) {
+    pub(crate) fn preprocess_meshes(
+        scene: &mut Scene,
+        grids: &Res<RenderResources>,
+        report: &mut LevelReport,
+    ) {
         let mut meshes_query = scene.world.query::<(&Handle<Mesh>, &Parent, Entity)>();
         let mut extras_map = HashMap::new();
+        // Nodes with several primitives share their extras, only report their errors once.
+        let mut parsed_parents = HashMap::new();
         for (_mesh, parent, id) in meshes_query.iter(&scene.world) {
-            let parent = scene.world.entity(**parent);
+            let parent_entity = **parent;
+            let parent = scene.world.entity(parent_entity);
             if let Some(extras) = parent.get::<GltfExtras>() {
-                match serde_json::from_str::<MeshExtras>(&extras.value) {
-                    Ok(mesh_extras) => {
-                        extras_map.insert(id, mesh_extras);
-                    }
-                    Err(e) => warn!("Deserializer error: {}", e),
+                let mesh_extras = parsed_parents.entry(parent_entity).or_insert_with(|| {
+                    let name = parent.get::<Name>().map(|n| n.as_str()).unwrap_or_default();
+                    parse_extras::<MeshExtras>(name, &extras.value, report)
+                });
+                if let Some(mesh_extras) = mesh_extras {
+                    extras_map.insert(id, mesh_extras.clone());
                 }
             }
         }
@@ -298,26 +407,47 @@ impl LevelProcessor {
                 entity.insert(grids.default_grid_material.clone());
             }
 
+            if let Some(false) = extras.portalable {
+                entity.insert(NonPortalable);
+            }
+
             entity.insert(extras.shape.unwrap_or_default());
         }
     }
 
     /// Modify the visibility components of nodes and add door trigger components.
-    pub(crate) fn preprocess_nodes(scene: &mut Scene, gltf: &Gltf) {
-        let mut nodes_query = scene
-            .world
-            .query_filtered::<(&GltfExtras, Entity), (With<Transform>, Without<Handle<Mesh>>)>();
+    pub(crate) fn preprocess_nodes(scene: &mut Scene, gltf: &Gltf, report: &mut LevelReport) {
+        let mut nodes_query = scene.world.query_filtered::<(&GltfExtras, Option<&Name>, Entity), (
+            With<Transform>,
+            Without<Handle<Mesh>>,
+        )>();
         let mut extras_map = HashMap::new();
-        for (extras, id) in nodes_query.iter(&scene.world) {
-            match serde_json::from_str::<NodeExtras>(&extras.value) {
-                Ok(node_extras) => {
-                    extras_map.insert(id, node_extras);
-                }
-                Err(e) => warn!("Deserializer error: {}", e),
+        for (extras, name, id) in nodes_query.iter(&scene.world) {
+            let name = name.map(|n| n.to_string()).unwrap_or_default();
+            if let Some(node_extras) = parse_extras::<NodeExtras>(&name, &extras.value, report) {
+                extras_map.insert(id, (name, node_extras));
             }
         }
 
-        for (id, extras) in extras_map {
+        // IDs which must be unique in the level, indexed by extra key and ID.
+        let mut unique_ids = HashMap::new();
+        let mut check_unique = |report: &mut LevelReport, node: &str, key: &str, id: String| {
+            if let Some(other_node) = unique_ids.get(&(key.to_owned(), id.clone())) {
+                report.push(
+                    node,
+                    Some(key),
+                    LevelValidationErrorKind::DuplicateId {
+                        id,
+                        other_node: other_node.clone(),
+                    },
+                );
+            } else {
+                unique_ids.insert((key.to_owned(), id), node.to_owned());
+            }
+        };
+
+        let mut portal_links = HashMap::new();
+        for (id, (name, extras)) in extras_map {
             let mut entity = scene.world.entity_mut(id);
 
             if let Some(door_trigger) = extras.door_trigger {
@@ -329,94 +459,284 @@ impl LevelProcessor {
             }
 
             if let Some(door_id) = extras.door {
-                entity.insert(Door {
-                    id: door_id,
-                    animation_open: gltf
-                        .named_animations
-                        .get(&format!("{}_{}", ANIMATION_OPEN_DOOR_PREFIX, door_id))
-                        .unwrap()
-                        .clone(),
-                    animation_close: gltf
-                        .named_animations
-                        .get(&format!("{}_{}", ANIMATION_CLOSE_DOOR_PREFIX, door_id))
-                        .unwrap()
-                        .clone(),
-                    ..default()
-                });
+                if let Some(animations) = Self::find_door_animations(gltf, report, &name, door_id) {
+                    entity.insert(Door {
+                        id: door_id,
+                        sidedness: extras.sidedness.unwrap_or_default(),
+                        locked: extras.locked.is_some(),
+                        unlock_signal: extras.locked,
+                        stay_open_for: extras.stay_open_for,
+                        animations,
+                        ..default()
+                    });
+                }
             }
 
             if let (Some(level), Some(close_door), Some(open_door)) =
                 (extras.level_transition, extras.close_door, extras.open_door)
             {
-                entity.insert(dbg!(SectionTransition {
-                    target_level: level,
+                // The door to open after a transition to another level belongs to that level, its
+                // animation is looked up once the level is spawned.
+                let close_animation = Self::find_door_animation(
+                    gltf,
+                    report,
+                    &name,
+                    "close_door",
+                    ANIMATION_CLOSE_DOOR_PREFIX,
                     close_door,
-                    open_door,
-                    close_animation: gltf
-                        .named_animations
-                        .get(&dbg!(format!(
-                            "{}_{}",
-                            ANIMATION_CLOSE_DOOR_PREFIX, close_door
-                        )))
-                        .unwrap()
-                        .clone(),
-                    open_animation: gltf
-                        .named_animations
-                        .get(&dbg!(format!(
-                            "{}_{}",
-                            ANIMATION_OPEN_DOOR_PREFIX, open_door
-                        )))
-                        .unwrap()
-                        .clone(),
-                }));
+                );
+                let open_animation = match extras.next_level {
+                    Some(_) => Some(Handle::default()),
+                    None => Self::find_door_animation(
+                        gltf,
+                        report,
+                        &name,
+                        "open_door",
+                        ANIMATION_OPEN_DOOR_PREFIX,
+                        open_door,
+                    ),
+                };
+                if let (Some(close_animation), Some(open_animation)) =
+                    (close_animation, open_animation)
+                {
+                    entity.insert(SectionTransition {
+                        target_level: level,
+                        next_level: extras.next_level,
+                        close_door,
+                        open_door,
+                        close_animation,
+                        open_animation,
+                    });
+                }
             }
 
             if let Some(section_start) = extras.section_start {
+                check_unique(report, &name, "section_start", section_start.clone());
                 entity.insert(SectionStart {
                     section_name: section_start,
                 });
             }
 
             if let Some(section_end) = extras.section_finish {
+                check_unique(report, &name, "section_finish", section_end.clone());
                 entity.insert(SectionFinish {
                     section_name: section_end,
                 });
             }
 
+            if let Some(section) = extras.section {
+                entity.insert(NodeSection {
+                    section_name: section,
+                });
+            }
+
             if let Some(pickup) = extras.pickup {
                 entity.insert(Pickup { id: pickup });
             }
 
             if let Some(pickup_sensor) = extras.pickup_sensor {
+                check_unique(report, &name, "pickup_sensor", pickup_sensor.to_string());
                 entity.insert(PickupSensor {
                     pickup_id: pickup_sensor,
+                    grants: extras.grants.unwrap_or_default(),
                 });
             }
+
+            if let Some(link) = extras.portal {
+                portal_links
+                    .entry(link.clone())
+                    .or_insert_with(Vec::new)
+                    .push(name.clone());
+                entity.insert(LevelPortal {
+                    link,
+                    color: extras.color,
+                    active: extras.active.unwrap_or(true),
+                });
+            }
+
+            if let Some(true) = extras.fizzler {
+                entity.insert(Fizzler);
+            }
+
+            if let Some(true) = extras.checkpoint {
+                entity.insert(Checkpoint);
+            }
+
+            if let Some(true) = extras.kill_volume {
+                entity.insert(KillVolume);
+            }
+
+            if let Some(true) = extras.laser_emitter {
+                entity.insert(LaserEmitter::default());
+            }
+
+            if let Some(doors_id) = extras.laser_receiver {
+                entity.insert(LaserReceiver {
+                    doors_id,
+                    ..default()
+                });
+            }
+
+            if let Some(true) = extras.laser_redirector {
+                entity.insert(LaserRedirector);
+            }
+
+            if let Some(button_id) = extras.floor_button {
+                let animation_press = Self::find_door_animation(
+                    gltf,
+                    report,
+                    &name,
+                    "floor_button",
+                    ANIMATION_PRESS_BUTTON_PREFIX,
+                    button_id,
+                );
+                let animation_release = Self::find_door_animation(
+                    gltf,
+                    report,
+                    &name,
+                    "floor_button",
+                    ANIMATION_RELEASE_BUTTON_PREFIX,
+                    button_id,
+                );
+                if extras.mass_threshold.is_none() {
+                    report.push(
+                        &name,
+                        Some("mass_threshold"),
+                        LevelValidationErrorKind::MissingMassThreshold,
+                    );
+                }
+                if let (Some(animation_press), Some(animation_release), Some(mass_threshold)) =
+                    (animation_press, animation_release, extras.mass_threshold)
+                {
+                    entity.insert(FloorButton {
+                        id: button_id,
+                        mass_threshold,
+                        animation_press,
+                        animation_release,
+                        ..default()
+                    });
+                }
+            }
+
+            if let Some(signal) = extras.signal {
+                if let Some(op) = extras.logic {
+                    entity.insert(LogicGate {
+                        op,
+                        inputs: extras.inputs.unwrap_or_default(),
+                        duration: extras.duration.unwrap_or_default(),
+                        ..default()
+                    });
+                } else if extras.door_trigger.is_none()
+                    && extras.laser_receiver.is_none()
+                    && extras.pickup_sensor.is_none()
+                    && extras.floor_button.is_none()
+                {
+                    entity.insert(SignalSensor::default());
+                }
+                entity.insert(SignalSource {
+                    signal,
+                    ..default()
+                });
+            }
+
+            if let Some(signal) = extras.on_signal {
+                entity.insert(SignalSink { signal });
+            }
         }
+        check_portal_links(report, portal_links);
 
-        let mut animator_query = scene
+        let animators = scene
             .world
-            .query_filtered::<Entity, With<AnimationPlayer>>();
-        for animator in scene
-            .world
-            .query_filtered::<&Name, With<AnimationPlayer>>()
+            .query_filtered::<Entity, With<AnimationPlayer>>()
             .iter(&scene.world)
-        {
-            info!("Got animator {}", animator);
+            .collect::<Vec<_>>();
+        // The animated nodes use their nearest animator, the scene animator is only a fallback when
+        // the whole scene shares a single animator.
+        if let [animator_entity] = animators[..] {
+            scene
+                .world
+                .entity_mut(animator_entity)
+                .insert(SceneAnimationPlayer);
+        } else if animators.is_empty() {
+            report.push(
+                "Scene",
+                None,
+                LevelValidationErrorKind::AnimatorCount(animators.len()),
+            );
         }
-        let animator_entity = animator_query.single(&scene.world);
-        scene
-            .world
-            .entity_mut(animator_entity)
-            .insert(SceneAnimationPlayer);
     }
 
+    /// Look up the animations of a door.
+    ///
+    /// Each side may have its own animations, suffixed with `_Left` or `_Right`, which default to
+    /// the animations shared by both sides.
+    fn find_door_animations(
+        gltf: &Gltf,
+        report: &mut LevelReport,
+        node: &str,
+        door_id: u32,
+    ) -> Option<DoorAnimations> {
+        let open = Self::find_door_animation(
+            gltf,
+            report,
+            node,
+            "door",
+            ANIMATION_OPEN_DOOR_PREFIX,
+            door_id,
+        );
+        let close = Self::find_door_animation(
+            gltf,
+            report,
+            node,
+            "door",
+            ANIMATION_CLOSE_DOOR_PREFIX,
+            door_id,
+        );
+        let (open, close) = (open?, close?);
+        let sided = |animation: &Handle<AnimationClip>, prefix: &str, side: DoorSidedness| {
+            gltf.named_animations
+                .get(&format!("{}_{}_{:?}", prefix, door_id, side))
+                .unwrap_or(animation)
+                .clone()
+        };
+        Some(DoorAnimations {
+            close_left: sided(&close, ANIMATION_CLOSE_DOOR_PREFIX, DoorSidedness::Left),
+            close_right: sided(&close, ANIMATION_CLOSE_DOOR_PREFIX, DoorSidedness::Right),
+            open_left: sided(&open, ANIMATION_OPEN_DOOR_PREFIX, DoorSidedness::Left),
+            open_right: sided(&open, ANIMATION_OPEN_DOOR_PREFIX, DoorSidedness::Right),
+        })
+    }
+
+    /// Look up a door animation by name, reporting it if it is missing from the glTF file.
+    fn find_door_animation(
+        gltf: &Gltf,
+        report: &mut LevelReport,
+        node: &str,
+        key: &str,
+        prefix: &str,
+        door_id: u32,
+    ) -> Option<Handle<AnimationClip>> {
+        let animation_name = format!("{}_{}", prefix, door_id);
+        let animation = gltf.named_animations.get(&animation_name).cloned();
+        if animation.is_none() {
+            report.push(
+                node,
+                Some(key),
+                LevelValidationErrorKind::MissingAnimation(animation_name),
+            );
+        }
+        animation
+    }
+
+    #[allow(clippy::too_many_arguments)]
     pub(crate) fn gltf_asset_event_listener(
         mut level_manager: ResMut<LevelProcessor>,
         mut scenes: ResMut<Assets<Scene>>,
         mut gltfs: ResMut<Assets<Gltf>>,
         mut events: EventReader<AssetEvent<Gltf>>,
         mut materials: ResMut<Assets<StandardMaterial>>,
+        mut reports: ResMut<Assets<LevelReport>>,
+        mut levels: ResMut<Assets<Level>>,
         grids: Res<RenderResources>,
     ) {
         for event in events.iter() {
@@ -428,14 +748,24 @@ impl LevelProcessor {
                         level_manager.hot_reloaded.remove(handle);
                         continue;
                     }
-                    if let Some(_level) = level_manager.loaded_levels_gltfs.get(handle) {
+                    if let Some(level) = level_manager
+                        .loaded_levels_gltfs
+                        .get(handle)
+                        .and_then(|level| levels.get_mut(level))
+                    {
                         let gltf = gltfs.get_mut(handle).unwrap();
-                        Self::update_level_on_gltf_reload(
+                        let report = Self::update_level_on_gltf_reload(
                             &mut scenes,
                             &mut materials,
                             &grids,
                             gltf,
+                            &level.name,
                         );
+                        if let Some(level_report) = reports.get_mut(&level.report) {
+                            *level_report = report;
+                        }
+                        level.sections = Self::find_sections(&mut scenes, gltf);
+                        level.section_links = Self::find_section_links(&mut scenes, gltf);
                         level_manager.hot_reloaded.insert(handle.to_owned());
                     }
                 }
@@ -451,12 +781,26 @@ impl LevelProcessor {
         mut door_sensors_query: Query<(&Name, &mut DoorSensor, &Children, Entity)>,
         level_transitions_query: Query<(&Name, &SectionTransition, &Children, Entity)>,
         fixed_geometry_query: Query<(&Name, &Handle<Mesh>, Option<&ColliderShape>, Entity)>,
-        dynamic_geometry_query: Query<(&Name, &Children, Entity)>,
+        dynamic_geometry_query: Query<(&Name, &Children, &Transform, Entity)>,
         doors_query: Query<(&Name, &Door, Entity)>,
-        pickups_sensors_query: Query<(&PickupSensor, &Children, Entity)>,
+        sensor_volumes_query: Query<
+            (&Name, Option<&Fizzler>, &Children, Entity),
+            Or<(
+                With<PickupSensor>,
+                With<Fizzler>,
+                With<SignalSensor>,
+                With<FloorButton>,
+                With<Checkpoint>,
+                With<KillVolume>,
+            )>,
+        >,
+        mut laser_receivers_query: Query<(&Name, &mut LaserReceiver, &Children, Entity)>,
+        level_portals_query: Query<(&LevelPortal, &Transform, Entity)>,
+        non_portalable_query: Query<(), With<NonPortalable>>,
         scene_instance_query: Query<&SceneInstance>,
         scene_spawner: Res<SceneSpawner>,
         meshes: Res<Assets<Mesh>>,
+        portal_res: Res<PortalResources>,
     ) {
         if let SpawnState::ProcessingScene(scene_entity) = level_manager.spawn_state {
             if let Ok(scene_id) = scene_instance_query.get(scene_entity) {
@@ -464,17 +808,24 @@ impl LevelProcessor {
                     let mut colliders = HashMap::new();
                     let mut doors = HashMap::new();
                     let mut sensors = Vec::new();
+                    let mut receivers = Vec::new();
+                    let mut portal_ends = HashMap::new();
                     for scene_entity in scene_spawner.iter_instance_entities(**scene_id) {
                         if let Ok((name, mesh_handle, opt_shape, entity)) =
                             fixed_geometry_query.get(scene_entity)
                         {
                             let shape = opt_shape.cloned().unwrap_or_default();
+                            let portalable_group = if non_portalable_query.contains(entity) {
+                                NON_PORTALABLE_GROUP
+                            } else {
+                                Group::NONE
+                            };
                             if name.ends_with(LEVEL_STATIC_GEOMETRY_SUFFIX) {
                                 let mesh = meshes.get(mesh_handle).unwrap();
 
                                 commands.entity(entity).insert((
                                     CollisionGroups::new(
-                                        WALLS_GROUP,
+                                        WALLS_GROUP | portalable_group,
                                         ALL_GROUPS - DOOR_SENSORS_GROUP,
                                     ),
                                     RigidBody::Fixed,
@@ -485,7 +836,7 @@ impl LevelProcessor {
 
                                 commands.entity(entity).insert((
                                     CollisionGroups::new(
-                                        GROUND_GROUP,
+                                        GROUND_GROUP | portalable_group,
                                         ALL_GROUPS - DOOR_SENSORS_GROUP,
                                     ),
                                     RigidBody::Fixed,
@@ -494,12 +845,13 @@ impl LevelProcessor {
                             }
                         }
 
-                        if let Ok((name, children, entity)) =
+                        if let Ok((name, children, transform, entity)) =
                             dynamic_geometry_query.get(scene_entity)
                         {
                             if name.ends_with(LEVEL_DYNAMIC_GEOMETRY_SUFFIX) {
-                                if let Ok((_name, mesh_handle, _opt_shape, _entity)) =
-                                    fixed_geometry_query.get(*children.first().unwrap())
+                                if let Some((_name, mesh_handle, _opt_shape, _entity)) = children
+                                    .first()
+                                    .and_then(|child| fixed_geometry_query.get(*child).ok())
                                 {
                                     let mesh = meshes.get(mesh_handle).unwrap();
                                     let collider =
@@ -515,6 +867,9 @@ impl LevelProcessor {
                                         Ccd::disabled(),
                                         collider.clone(),
                                         PortalTeleport,
+                                        LevelProp {
+                                            authored_transform: *transform,
+                                        },
                                     ));
                                 } else {
                                     warn!("Dynamic geometry node without a child mesh");
@@ -526,11 +881,12 @@ impl LevelProcessor {
                             doors.entry(door.id).or_insert_with(Vec::new).push(entity);
                         }
 
-                        if let Ok((_name, _sensor, children, entity)) =
+                        if let Ok((name, _sensor, children, entity)) =
                             door_sensors_query.get_mut(scene_entity)
                         {
-                            if let Ok((_, mesh_handle, opt_shape, _)) =
-                                fixed_geometry_query.get(*children.first().unwrap())
+                            if let Some((_, mesh_handle, opt_shape, _)) = children
+                                .first()
+                                .and_then(|child| fixed_geometry_query.get(*child).ok())
                             {
                                 let mesh = meshes.get(mesh_handle).unwrap();
                                 let shape = opt_shape.cloned().unwrap_or_default();
@@ -545,14 +901,17 @@ impl LevelProcessor {
                                     ActiveEvents::COLLISION_EVENTS,
                                 ));
                                 sensors.push(entity);
+                            } else {
+                                warn!("Door sensor {} without a child mesh", name);
                             }
                         }
 
-                        if let Ok((_name, _transition, children, entity)) =
+                        if let Ok((name, _transition, children, entity)) =
                             level_transitions_query.get(scene_entity)
                         {
-                            if let Ok((_, mesh_handle, opt_shape, _)) =
-                                fixed_geometry_query.get(*children.first().unwrap())
+                            if let Some((_, mesh_handle, opt_shape, _)) = children
+                                .first()
+                                .and_then(|child| fixed_geometry_query.get(*child).ok())
                             {
                                 info!("Creating level transition to {}", _transition.target_level);
                                 let mesh = meshes.get(mesh_handle).unwrap();
@@ -567,14 +926,19 @@ impl LevelProcessor {
                                     ),
                                     ActiveEvents::COLLISION_EVENTS,
                                 ));
+                            } else {
+                                warn!("Level transition {} without a child mesh", name);
                             }
                         }
 
-                        if let Ok((_pickup_sensor, children, entity)) =
-                            pickups_sensors_query.get(scene_entity)
+                        // Pickup sensors, fizzlers, signal sensors, floor buttons, checkpoints and
+                        // kill volumes.
+                        if let Ok((name, fizzler, children, entity)) =
+                            sensor_volumes_query.get(scene_entity)
                         {
-                            if let Ok((_, mesh_handle, opt_shape, _)) =
-                                fixed_geometry_query.get(*children.first().unwrap())
+                            if let Some((_, mesh_handle, opt_shape, _)) = children
+                                .first()
+                                .and_then(|child| fixed_geometry_query.get(*child).ok())
                             {
                                 let mesh = meshes.get(mesh_handle).unwrap();
                                 let shape = opt_shape.cloned().unwrap_or_default();
@@ -588,10 +952,69 @@ impl LevelProcessor {
                                     ),
                                     ActiveEvents::COLLISION_EVENTS,
                                 ));
+                                if fizzler.is_some() {
+                                    // The props carried by the player are kinematic.
+                                    commands.entity(entity).insert(
+                                        ActiveCollisionTypes::default()
+                                            | ActiveCollisionTypes::KINEMATIC_STATIC,
+                                    );
+                                }
+                            } else {
+                                warn!("Sensor volume {} without a child mesh", name);
+                            }
+                        }
+
+                        if let Ok((name, _receiver, children, entity)) =
+                            laser_receivers_query.get(scene_entity)
+                        {
+                            if let Some((_, mesh_handle, opt_shape, _)) = children
+                                .first()
+                                .and_then(|child| fixed_geometry_query.get(*child).ok())
+                            {
+                                let mesh = meshes.get(mesh_handle).unwrap();
+                                let shape = opt_shape.cloned().unwrap_or_default();
+                                // Only hit by the laser beams.
+                                commands.entity(entity).insert((
+                                    RigidBody::Fixed,
+                                    Self::compute_collider(mesh, shape),
+                                    Sensor,
+                                    CollisionGroups::new(LASER_RECEIVERS_GROUP, RAYCAST_GROUP),
+                                ));
+                                receivers.push(entity);
+                            } else {
+                                warn!("Laser receiver {} without a child mesh", name);
                             }
                         }
                     }
 
+                    for scene_entity in scene_spawner.iter_instance_entities(**scene_id) {
+                        if let Ok((level_portal, transform, entity)) =
+                            level_portals_query.get(scene_entity)
+                        {
+                            let count = portal_ends
+                                .entry(level_portal.link.clone())
+                                .or_insert(0usize);
+                            let end = match *count {
+                                0 => PortalEnd::A,
+                                1 => PortalEnd::B,
+                                _ => {
+                                    warn!("Extra portal with link {}", level_portal.link);
+                                    continue;
+                                }
+                            };
+                            *count += 1;
+                            let portal = commands
+                                .spawn(PortalBundle::from_level_node(
+                                    level_portal,
+                                    end,
+                                    transform,
+                                    &portal_res,
+                                ))
+                                .id();
+                            commands.entity(entity).add_child(portal);
+                        }
+                    }
+
                     for sensor_entity in sensors {
                         if let Ok((name, mut sensor, _, _)) =
                             door_sensors_query.get_mut(sensor_entity)
@@ -607,6 +1030,21 @@ impl LevelProcessor {
                         }
                     }
 
+                    for receiver_entity in receivers {
+                        if let Ok((name, mut receiver, _, _)) =
+                            laser_receivers_query.get_mut(receiver_entity)
+                        {
+                            if let Some(receiver_doors) = doors.get(&receiver.doors_id) {
+                                receiver.door_entities = receiver_doors.clone()
+                            } else {
+                                warn!(
+                                    "No doors found for laser receiver {} with ID {}",
+                                    name, receiver.doors_id
+                                );
+                            }
+                        }
+                    }
+
                     info!("Level geometry processed");
                     level_manager.spawn_state = SpawnState::Spawning;
                     level_manager.current_level_root = Some(scene_entity);
@@ -629,7 +1067,9 @@ impl LevelProcessor {
             }
 
             if let Some(player) = level_manager.player_entity {
-                commands.entity(player).despawn_recursive();
+                if level_manager.carried_player.is_none() {
+                    commands.entity(player).despawn_recursive();
+                }
             }
 
             let scene_instance = commands
@@ -644,20 +1084,28 @@ impl LevelProcessor {
         }
     }
 
+    #[allow(clippy::too_many_arguments)]
     pub(crate) fn check_level_loading_progress(
         mut level_manager: ResMut<LevelProcessor>,
         mut levels: ResMut<Assets<Level>>,
         mut scenes: ResMut<Assets<Scene>>,
         mut gltfs: ResMut<Assets<Gltf>>,
         mut materials: ResMut<Assets<StandardMaterial>>,
+        mut reports: ResMut<Assets<LevelReport>>,
+        mut progress: ResMut<LevelLoadProgress>,
         grid_materials: Res<RenderResources>,
         asset_server: Res<AssetServer>,
     ) {
         if !level_manager.loading_levels.is_empty() {
             let mut loaded_levels = Vec::new();
+            let mut failed_levels = Vec::new();
 
             for (level_name, level_gltf) in &level_manager.loading_levels {
-                if asset_server.get_load_state(level_gltf) == bevy::asset::LoadState::Loaded {
+                let load_state = asset_server.get_load_state(level_gltf);
+                if load_state == bevy::asset::LoadState::Failed {
+                    error!("Level {} failed to load", level_name);
+                    failed_levels.push(level_name.to_owned());
+                } else if load_state == bevy::asset::LoadState::Loaded {
                     let gltf = gltfs
                         .get_mut(level_gltf)
                         .expect("Wasn't able to obtain GLTF though Bevy says it's loaded");
@@ -665,6 +1113,7 @@ impl LevelProcessor {
                         &mut levels,
                         &mut scenes,
                         &mut materials,
+                        &mut reports,
                         gltf,
                         level_gltf,
                         level_name,
@@ -674,12 +1123,127 @@ impl LevelProcessor {
                 }
             }
 
+            for level_name in failed_levels {
+                level_manager.loading_levels.remove(&level_name);
+                level_manager.loaded_count += 1;
+                progress.failed.push(level_name);
+            }
+
             for (level_name, handle, gltf) in loaded_levels {
                 level_manager.loading_levels.remove(&level_name);
                 level_manager
                     .loaded_levels
                     .insert(level_name, handle.clone());
                 level_manager.loaded_levels_gltfs.insert(gltf, handle);
+                level_manager.loaded_count += 1;
+            }
+
+            progress.requested = level_manager.requested_count;
+            progress.loaded = level_manager.loaded_count;
+        }
+    }
+
+    /// Preload the levels of the sections next to the current one and unload the levels whose
+    /// sections are all too far away, every time the player enters a new section.
+    #[allow(clippy::too_many_arguments)]
+    pub(crate) fn stream_levels(
+        mut commands: Commands,
+        mut level_manager: ResMut<LevelProcessor>,
+        mut levels: ResMut<Assets<Level>>,
+        mut progress: ResMut<LevelLoadProgress>,
+        current_level: Res<CurrentLevel>,
+        streaming: Res<LevelStreaming>,
+        scene_roots_query: Query<(&Handle<Scene>, Entity)>,
+        asset_server: Res<AssetServer>,
+    ) {
+        if !current_level.is_changed() {
+            return;
+        }
+        let current_name = match levels.get(&current_level.get()) {
+            Some(level) => level.name.clone(),
+            None => return,
+        };
+        progress.failed.clear();
+
+        // Breadth first search of the section transition graph starting from the current
+        // section, only the loaded levels' transitions are known. The distance of a level is the
+        // one of its closest section.
+        let mut visited = HashSet::new();
+        let mut distances = HashMap::new();
+        let mut queue = VecDeque::from([(current_name, current_level.current_section(), 0)]);
+        while let Some((level_name, section, distance)) = queue.pop_front() {
+            if !visited.insert((level_name.clone(), section.clone())) {
+                continue;
+            }
+            if let Some(level) = level_manager
+                .loaded_levels
+                .get(&level_name)
+                .and_then(|handle| levels.get(handle))
+            {
+                for link in &level.section_links {
+                    let from_section = link.from_section.as_ref();
+                    if from_section.map_or(true, |from| from.section_name == section) {
+                        let next_level = link.to_level.as_ref().unwrap_or(&level_name);
+                        queue.push_back((
+                            next_level.clone(),
+                            link.to_section.clone(),
+                            distance + 1,
+                        ));
+                    }
+                }
+            }
+            distances.entry(level_name).or_insert(distance);
+        }
+
+        for (name, distance) in &distances {
+            if *distance == 1 {
+                if let Err(e) = level_manager.preload_level(name, &asset_server) {
+                    warn!("Can not preload level {}: {}", name, e);
+                }
+            }
+        }
+
+        let far_levels = level_manager
+            .loaded_levels
+            .keys()
+            .filter(|name| {
+                distances
+                    .get(*name)
+                    .map_or(true, |distance| *distance > streaming.unload_distance)
+            })
+            .cloned()
+            .collect::<Vec<_>>();
+        for name in far_levels {
+            level_manager.unload_level(&mut commands, &name, &mut levels, &scene_roots_query);
+        }
+    }
+
+    /// Load the first level listed in the manifest when it is loaded or modified.
+    pub(crate) fn manifest_event_listener(
+        mut level_manager: ResMut<LevelProcessor>,
+        mut events: EventReader<AssetEvent<LevelManifest>>,
+        manifests: Res<Assets<LevelManifest>>,
+        asset_server: Res<AssetServer>,
+    ) {
+        for event in events.iter() {
+            match event {
+                AssetEvent::Created { handle } | AssetEvent::Modified { handle } => {
+                    if *handle != level_manager.manifest {
+                        continue;
+                    }
+                    if let Some(manifest) = manifests.get(handle) {
+                        level_manager.manifest_levels = manifest.levels.clone();
+                        // The other levels are streamed in as the player progresses
+                        if let Some(first_level) = manifest.levels.first() {
+                            if let Err(e) =
+                                level_manager.preload_level(&first_level.name, &asset_server)
+                            {
+                                error!("Can not load level {}: {}", first_level.name, e);
+                            }
+                        }
+                    }
+                }
+                AssetEvent::Removed { handle: _ } => {}
             }
         }
     }
@@ -688,12 +1252,39 @@ impl LevelProcessor {
         mut commands: Commands,
         mut level_manager: ResMut<LevelProcessor>,
         spawn_points_query: Query<(&SectionStart, &Transform)>,
+        levels: Res<Assets<Level>>,
     ) {
         if level_manager.spawn_state == SpawnState::Spawning {
+            if let (Some(carried), Some(player_entity)) =
+                (&level_manager.carried_player, level_manager.player_entity)
+            {
+                let spawn_node = spawn_points_query
+                    .into_iter()
+                    .find(|(section, _trf)| section.section_name == carried.section)
+                    .expect("No spawn point for the transition target section")
+                    .1;
+                let velocity = Velocity {
+                    linvel: spawn_node.rotation * carried.velocity.linvel,
+                    angvel: carried.velocity.angvel,
+                };
+                commands.entity(player_entity).insert((
+                    spawn_node.mul_transform(carried.transform),
+                    velocity,
+                    RigidBody::Dynamic,
+                ));
+
+                level_manager.spawn_state = SpawnState::Finalizing;
+                commands.insert_resource(NextState(GameState::InGame));
+                return;
+            }
+
+            let start_section = level_manager
+                .current_start_section(&levels)
+                .expect("Level is not listed in the manifest");
             let spawn_node = spawn_points_query
                 .into_iter()
-                .find(|(section, _trf)| dbg!(&section.section_name) == LEVEL_LIST[0])
-                .unwrap()
+                .find(|(section, _trf)| section.section_name == start_section)
+                .expect("No spawn point for the level start section")
                 .1
                 .to_owned();
             let player_entity = commands
@@ -716,38 +1307,42 @@ impl LevelProcessor {
     pub(crate) fn finalize_level_spawn(
         mut commands: Commands,
         mut level_manager: ResMut<LevelProcessor>,
+        levels: Res<Assets<Level>>,
     ) {
         if level_manager.spawn_state == SpawnState::Finalizing
             && level_manager.current_level_root.is_some()
             && level_manager.player_entity.is_some()
         {
             info!("Marking level spawn as complete, transitioning to in game state");
+            let section = match level_manager.carried_player.take() {
+                Some(carried) => carried.section,
+                None => level_manager
+                    .current_start_section(&levels)
+                    .unwrap_or_default(),
+            };
             commands.insert_resource(CurrentLevel {
                 level: level_manager.current_level().unwrap(),
-                section: "Level1".to_owned(),
+                section,
             });
             level_manager.spawn_state = SpawnState::Idle;
         }
     }
 
     // Private methods
+    #[allow(clippy::too_many_arguments)]
     fn process_gltf_levels(
         levels: &mut ResMut<Assets<Level>>,
         scenes: &mut ResMut<Assets<Scene>>,
         materials: &mut ResMut<Assets<StandardMaterial>>,
+        reports: &mut ResMut<Assets<LevelReport>>,
         gltf: &mut Gltf,
         handle: &Handle<Gltf>,
         level_name: &str,
         grids: &Res<RenderResources>,
     ) -> Handle<Level> {
-        let default_scene_handle = gltf.default_scene.as_ref().unwrap();
-        let default_scene = scenes.get_mut(default_scene_handle).unwrap();
-
-        // Add required components to the entities in the scene's world based on the GltfExtras
-        Self::preprocess_point_lights(default_scene);
-        Self::preprocess_nodes(default_scene, gltf);
-        Self::preprocess_meshes(default_scene, grids);
-        Self::preprocess_materials(default_scene, materials);
+        let report = Self::update_level_on_gltf_reload(scenes, materials, grids, gltf, level_name);
+        let sections = Self::find_sections(scenes, gltf);
+        let section_links = Self::find_section_links(scenes, gltf);
         let level = Level::new(
             handle.to_owned(),
             // No need for strong handles if we're keeping a handle to the level besides the
@@ -757,22 +1352,61 @@ impl LevelProcessor {
                 .expect("GLTF asset has no default scene")
                 .cast_weak(),
             level_name.to_owned(),
+            reports.add(report),
+            sections,
+            section_links,
         );
         levels.add(level)
     }
 
+    /// Collect the names of the sections of the preprocessed scene.
+    fn find_sections(scenes: &mut ResMut<Assets<Scene>>, gltf: &Gltf) -> Vec<String> {
+        let scene = scenes
+            .get_mut(gltf.default_scene.as_ref().unwrap())
+            .unwrap();
+        scene
+            .world
+            .query::<&SectionStart>()
+            .iter(&scene.world)
+            .map(|start| start.section_name.clone())
+            .collect()
+    }
+
+    /// Collect the section transitions of the preprocessed scene.
+    fn find_section_links(scenes: &mut ResMut<Assets<Scene>>, gltf: &Gltf) -> Vec<SectionLink> {
+        let scene = scenes
+            .get_mut(gltf.default_scene.as_ref().unwrap())
+            .unwrap();
+        scene
+            .world
+            .query::<(&SectionTransition, Option<&NodeSection>)>()
+            .iter(&scene.world)
+            .map(|(transition, section)| SectionLink {
+                from_section: section.cloned(),
+                to_section: transition.target_level.clone(),
+                to_level: transition.next_level.clone(),
+            })
+            .collect()
+    }
+
+    /// Add required components to the entities in the scene's world based on the GltfExtras,
+    /// and return the validation report of the level.
     fn update_level_on_gltf_reload(
         scenes: &mut ResMut<Assets<Scene>>,
         materials: &mut ResMut<Assets<StandardMaterial>>,
         grids: &Res<RenderResources>,
         gltf: &mut Gltf,
-    ) {
+        level_name: &str,
+    ) -> LevelReport {
+        let mut report = LevelReport::new(level_name);
         let default_scene_handle = gltf.default_scene.as_ref().unwrap();
         let default_scene = scenes.get_mut(default_scene_handle).unwrap();
-        Self::preprocess_point_lights(default_scene);
-        Self::preprocess_nodes(default_scene, gltf);
-        Self::preprocess_meshes(default_scene, grids);
-        Self::preprocess_materials(default_scene, materials);
+        Self::preprocess_point_lights(default_scene, &mut report);
+        Self::preprocess_nodes(default_scene, gltf, &mut report);
+        Self::preprocess_meshes(default_scene, grids, &mut report);
+        Self::preprocess_materials(default_scene, materials, &mut report);
+        report.log();
+        report
     }
 
     fn compute_collider(mesh: &Mesh, shape: ColliderShape) -> Collider {
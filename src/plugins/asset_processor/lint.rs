@@ -0,0 +1,324 @@
+//! Offline checks of level glTF files, which require neither spawning the level nor a GPU.
+//!
+//! The extras are parsed with the same deserializers as the [`LevelProcessor`], and the problems
+//! are collected into a [`LevelReport`].
+//!
+//! [`LevelProcessor`]: super::LevelProcessor
+
+use bevy::utils::{HashMap, HashSet};
+
+use super::{
+    extras::*, validation::*, ANIMATION_CLOSE_DOOR_PREFIX, ANIMATION_OPEN_DOOR_PREFIX,
+    ANIMATION_PRESS_BUTTON_PREFIX, ANIMATION_RELEASE_BUTTON_PREFIX, LEVEL_DYNAMIC_GEOMETRY_SUFFIX,
+};
+
+/// Check the extras of the default scene of a level glTF document.
+pub fn lint_level(document: &gltf::Document, level_name: &str) -> LevelReport {
+    let mut report = LevelReport::new(level_name);
+    let scene = match document
+        .default_scene()
+        .or_else(|| document.scenes().next())
+    {
+        Some(scene) => scene,
+        None => {
+            report.push(level_name, None, LevelValidationErrorKind::MissingScene);
+            return report;
+        }
+    };
+
+    // Flatten the scene hierarchy, remembering the root node of every node.
+    let mut nodes = Vec::new();
+    for root in scene.nodes() {
+        let root_index = root.index();
+        collect_nodes(root, root_index, &mut nodes);
+    }
+
+    let animations = document
+        .animations()
+        .filter_map(|animation| animation.name())
+        .collect::<HashSet<_>>();
+    let check_animation = |report: &mut LevelReport, node: &str, key: &str, name: String| {
+        if !animations.contains(name.as_str()) {
+            report.push(
+                node,
+                Some(key),
+                LevelValidationErrorKind::MissingAnimation(name),
+            );
+        }
+    };
+
+    let mut door_ids = HashSet::new();
+    let mut door_nodes = Vec::new();
+    let mut door_triggers = Vec::new();
+    let mut section_refs = Vec::new();
+    let mut section_starts = HashMap::new();
+    let mut section_finishes = HashSet::new();
+    let mut portal_links = HashMap::new();
+    let mut signals = HashSet::new();
+    let mut signal_inputs = Vec::new();
+    let mut unique_ids = HashMap::new();
+    let mut check_unique = |report: &mut LevelReport, node: &str, key: &str, id: String| {
+        if let Some(other_node) = unique_ids.get(&(key.to_owned(), id.clone())) {
+            report.push(
+                node,
+                Some(key),
+                LevelValidationErrorKind::DuplicateId {
+                    id,
+                    other_node: other_node.clone(),
+                },
+            );
+        } else {
+            unique_ids.insert((key.to_owned(), id), node.to_owned());
+        }
+    };
+
+    for (node, root) in &nodes {
+        let name = node
+            .name()
+            .map(str::to_owned)
+            .unwrap_or_else(|| format!("GltfNode{}", node.index()));
+
+        let mut has_section = false;
+        if let Some(extras) = node.extras() {
+            if node.mesh().is_some() {
+                parse_extras::<MeshExtras>(&name, extras.get(), &mut report);
+            }
+
+            if let Some(extras) = parse_extras::<NodeExtras>(&name, extras.get(), &mut report) {
+                if let Some(door_id) = extras.door {
+                    door_ids.insert(door_id);
+                    door_nodes.push((name.clone(), door_id, *root));
+                    for prefix in [ANIMATION_OPEN_DOOR_PREFIX, ANIMATION_CLOSE_DOOR_PREFIX] {
+                        let animation = format!("{}_{}", prefix, door_id);
+                        check_animation(&mut report, &name, "door", animation);
+                    }
+                }
+
+                if let Some(button_id) = extras.floor_button {
+                    for prefix in [
+                        ANIMATION_PRESS_BUTTON_PREFIX,
+                        ANIMATION_RELEASE_BUTTON_PREFIX,
+                    ] {
+                        let animation = format!("{}_{}", prefix, button_id);
+                        check_animation(&mut report, &name, "floor_button", animation);
+                    }
+                    if extras.mass_threshold.is_none() {
+                        report.push(
+                            &name,
+                            Some("mass_threshold"),
+                            LevelValidationErrorKind::MissingMassThreshold,
+                        );
+                    }
+                }
+
+                if let Some(door_trigger) = extras.door_trigger {
+                    door_triggers.push((name.clone(), "door_trigger", door_trigger));
+                }
+
+                if let Some(laser_receiver) = extras.laser_receiver {
+                    door_triggers.push((name.clone(), "laser_receiver", laser_receiver));
+                }
+
+                if let Some(target) = extras.level_transition {
+                    if let Some(close_door) = extras.close_door {
+                        let animation = format!("{}_{}", ANIMATION_CLOSE_DOOR_PREFIX, close_door);
+                        check_animation(&mut report, &name, "close_door", animation);
+                    }
+                    // Transitions to another level open a door of that level.
+                    if extras.next_level.is_none() {
+                        if let Some(open_door) = extras.open_door {
+                            let animation = format!("{}_{}", ANIMATION_OPEN_DOOR_PREFIX, open_door);
+                            check_animation(&mut report, &name, "open_door", animation);
+                        }
+                        section_refs.push((name.clone(), "level_transition", target));
+                    }
+                }
+
+                if let Some(section) = extras.section_start {
+                    check_unique(&mut report, &name, "section_start", section.clone());
+                    section_starts.insert(section, name.clone());
+                }
+
+                if let Some(section) = extras.section_finish {
+                    check_unique(&mut report, &name, "section_finish", section.clone());
+                    section_finishes.insert(section);
+                }
+
+                if let Some(section) = extras.section {
+                    has_section = true;
+                    section_refs.push((name.clone(), "section", section));
+                }
+
+                if let Some(link) = extras.portal {
+                    portal_links
+                        .entry(link)
+                        .or_insert_with(Vec::new)
+                        .push(name.clone());
+                }
+
+                if let Some(signal) = extras.signal {
+                    signals.insert(signal);
+                }
+
+                for input in extras.inputs.unwrap_or_default() {
+                    signal_inputs.push((name.clone(), "inputs", input));
+                }
+
+                if let Some(signal) = extras.locked {
+                    signal_inputs.push((name.clone(), "locked", signal));
+                }
+
+                if let Some(signal) = extras.on_signal {
+                    signal_inputs.push((name.clone(), "on_signal", signal));
+                }
+
+                if let Some(pickup_sensor) = extras.pickup_sensor {
+                    check_unique(
+                        &mut report,
+                        &name,
+                        "pickup_sensor",
+                        pickup_sensor.to_string(),
+                    );
+                }
+            }
+        }
+
+        if let Some(mesh) = node.mesh() {
+            let mesh_name = mesh.name().unwrap_or(&name);
+            for primitive in mesh.primitives() {
+                if let Some(extras) = primitive.extras() {
+                    parse_extras::<MaterialExtras>(mesh_name, extras.get(), &mut report);
+                }
+            }
+        } else if name.ends_with(LEVEL_DYNAMIC_GEOMETRY_SUFFIX) {
+            report.push(&name, None, LevelValidationErrorKind::MissingPropMesh);
+        }
+
+        // Props without a section are never reset.
+        if name.ends_with(LEVEL_DYNAMIC_GEOMETRY_SUFFIX) && !has_section {
+            report.push(&name, None, LevelValidationErrorKind::MissingPropSection);
+        }
+
+        if let Some(light) = node.light() {
+            if let Some(extras) = light.extras() {
+                parse_extras::<LightExtras>(&name, extras.get(), &mut report);
+            }
+        }
+    }
+
+    for (node, key, door_trigger) in door_triggers {
+        if !door_ids.contains(&door_trigger) {
+            report.push(
+                &node,
+                Some(key),
+                LevelValidationErrorKind::MissingDoor(door_trigger),
+            );
+        }
+    }
+
+    for (node, key, signal) in signal_inputs {
+        if !signals.contains(&signal) {
+            report.push(
+                &node,
+                Some(key),
+                LevelValidationErrorKind::MissingSignal(signal),
+            );
+        }
+    }
+
+    for (node, key, section) in section_refs {
+        if !section_starts.contains_key(&section) {
+            report.push(
+                &node,
+                Some(key),
+                LevelValidationErrorKind::MissingSectionStart(section),
+            );
+        }
+    }
+
+    let mut section_starts = section_starts.into_iter().collect::<Vec<_>>();
+    section_starts.sort();
+    for (section, node) in section_starts {
+        if !section_finishes.contains(&section) {
+            report.push(
+                &node,
+                Some("section_start"),
+                LevelValidationErrorKind::MissingSectionFinish(section),
+            );
+        }
+    }
+
+    check_portal_links(&mut report, portal_links);
+
+    // Bevy inserts an animation player on the root node of every animated hierarchy.
+    let roots = nodes
+        .iter()
+        .map(|(node, root)| (node.index(), *root))
+        .collect::<HashMap<_, _>>();
+    let animated_roots = document
+        .animations()
+        .flat_map(|animation| animation.channels())
+        .filter_map(|channel| roots.get(&channel.target().node().index()).copied())
+        .collect::<HashSet<_>>();
+    if animated_roots.is_empty() {
+        report.push(
+            "Scene",
+            None,
+            LevelValidationErrorKind::AnimatorCount(animated_roots.len()),
+        );
+    }
+
+    // The doors play their animations on the animation player of their root node.
+    let mut door_animators = HashMap::new();
+    for (node, door, root) in door_nodes {
+        if !animated_roots.contains(&root) {
+            continue;
+        }
+        match door_animators.get(&root) {
+            Some(other_door) if *other_door != door => report.push(
+                &node,
+                Some("door"),
+                LevelValidationErrorKind::SharedDoorAnimator {
+                    door,
+                    other_door: *other_door,
+                },
+            ),
+            Some(_) => {}
+            None => {
+                door_animators.insert(root, door);
+            }
+        }
+    }
+
+    report
+}
+
+/// Report the portal links which don't pair exactly two nodes.
+pub(crate) fn check_portal_links(
+    report: &mut LevelReport,
+    portal_links: HashMap<String, Vec<String>>,
+) {
+    let mut portal_links = portal_links.into_iter().collect::<Vec<_>>();
+    portal_links.sort();
+    for (link, nodes) in portal_links {
+        if nodes.len() != 2 {
+            for node in &nodes {
+                report.push(
+                    node,
+                    Some("portal"),
+                    LevelValidationErrorKind::UnpairedPortal {
+                        link: link.clone(),
+                        count: nodes.len(),
+                    },
+                );
+            }
+        }
+    }
+}
+
+fn collect_nodes<'a>(node: gltf::Node<'a>, root: usize, nodes: &mut Vec<(gltf::Node<'a>, usize)>) {
+    for child in node.children() {
+        collect_nodes(child, root, nodes);
+    }
+    nodes.push((node, root));
+}
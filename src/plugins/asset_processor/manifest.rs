@@ -0,0 +1,58 @@
+//! Level manifest, listing the levels of the game so new ones can be added without recompiling.
+//!
+//! The manifest is a JSON file with the `.manifest` extension, e.g.:
+//!
+//! ```json
+//! {
+//!   "levels": [
+//!     { "name": "lobby", "path": "levels/level1.glb", "start_section": "Level1" }
+//!   ]
+//! }
+//! ```
+//!
+//! The levels are played in the order they are listed in.
+
+use bevy::{
+    asset::{AssetLoader, BoxedFuture, LoadContext, LoadedAsset},
+    reflect::TypeUuid,
+};
+use serde::Deserialize;
+
+pub const LEVEL_MANIFEST_FILE: &str = "levels.manifest";
+
+#[derive(Debug, Clone, Deserialize, PartialEq, Eq)]
+pub struct LevelManifestEntry {
+    /// Name the level is referred to by.
+    pub name: String,
+    /// Path of the level glTF file, relative to the assets directory.
+    pub path: String,
+    /// Name of the section the player spawns in when the level is instantiated.
+    pub start_section: String,
+}
+
+#[derive(Debug, Clone, Default, Deserialize, TypeUuid)]
+#[uuid = "d2a6b1f3-3c7e-4f0a-b5e4-7a9c8e1f2d60"]
+pub struct LevelManifest {
+    pub levels: Vec<LevelManifestEntry>,
+}
+
+#[derive(Debug, Default)]
+pub struct LevelManifestLoader;
+
+impl AssetLoader for LevelManifestLoader {
+    fn load<'a>(
+        &'a self,
+        bytes: &'a [u8],
+        load_context: &'a mut LoadContext,
+    ) -> BoxedFuture<'a, Result<(), bevy::asset::Error>> {
+        Box::pin(async move {
+            let manifest = serde_json::from_slice::<LevelManifest>(bytes)?;
+            load_context.set_default_asset(LoadedAsset::new(manifest));
+            Ok(())
+        })
+    }
+
+    fn extensions(&self) -> &[&str] {
+        &["manifest"]
+    }
+}
@@ -1,13 +1,21 @@
 use bevy::prelude::*;
 use iyes_loopless::{prelude::*, state::StateTransitionStageLabel};
 
+mod animators;
+mod extras;
 mod level;
 mod level_processor;
+mod lint;
+mod manifest;
+mod validation;
 
+pub use animators::*;
+pub use extras::*;
 pub use level::*;
 pub use level_processor::*;
-
-use self::level_processor::ColliderShape;
+pub use lint::*;
+pub use manifest::*;
+pub use validation::*;
 
 use super::game::GameState;
 
@@ -25,13 +33,20 @@ pub struct LevelsPlugin;
 
 impl Plugin for LevelsPlugin {
     fn build(&self, app: &mut App) {
-        app.add_asset::<Level>();
+        app.add_asset::<Level>()
+            .add_asset::<LevelReport>()
+            .add_asset::<LevelManifest>()
+            .init_asset_loader::<LevelManifestLoader>();
         app.register_type::<SceneAnimationPlayer>()
             .register_type::<SectionTransition>()
             .register_type::<SectionStart>()
             .register_type::<SectionFinish>()
+            .register_type::<LevelProp>()
+            .register_type::<NodeSection>()
             .register_type::<ColliderShape>();
-        app.insert_resource(LevelProcessor::new());
+        app.insert_resource(LevelProcessor::new())
+            .init_resource::<LevelStreaming>()
+            .init_resource::<LevelLoadProgress>();
 
         app.add_enter_system(GameState::Loading, LevelProcessor::init_level_transition);
         app.add_exit_system(GameState::Loading, LevelProcessor::finalize_level_spawn);
@@ -72,8 +87,11 @@ impl Plugin for LevelsPlugin {
                 .after(PrepareStageSystemLabels::SpawnPlayer),
         );
 
+        app.add_system(LevelProcessor::manifest_event_listener);
         app.add_system(LevelProcessor::gltf_asset_event_listener);
         app.add_system(LevelProcessor::check_level_loading_progress);
+        app.add_system(LevelProcessor::stream_levels.run_in_state(GameState::InGame));
+        app.add_system(find_node_animators);
 
         app.add_enter_system(GameState::InGame, init_section_table);
 
@@ -88,6 +106,12 @@ impl Plugin for LevelsPlugin {
                 .label(SectionTransitionLabels::PerformTransition)
                 .after(SectionTransitionLabels::InitiateTransition),
         );
+        app.add_system(
+            perform_level_transition
+                .run_in_state(GameState::InGame)
+                .label(SectionTransitionLabels::PerformTransition)
+                .after(SectionTransitionLabels::InitiateTransition),
+        );
     }
 }
 
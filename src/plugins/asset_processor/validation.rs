@@ -0,0 +1,197 @@
+//! Validation of the glTF extras authored on the level nodes, meshes and materials.
+//!
+//! Problems found while preprocessing a level are collected into a [`LevelReport`] asset instead
+//! of aborting, so a typo in Blender doesn't bring the game down on hot reload.
+
+use std::fmt;
+
+use bevy::{prelude::*, reflect::TypeUuid};
+use serde::de::DeserializeOwned;
+use serde_json::{Map, Value};
+
+/// The different kinds of problems that can be found in a level.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum LevelValidationErrorKind {
+    /// The extra value could not be deserialized.
+    InvalidValue(String),
+    /// The extra refers to an animation which is not present in the glTF file.
+    MissingAnimation(String),
+    /// The extra ID must be unique in the level, but is already used by another node.
+    DuplicateId { id: String, other_node: String },
+    /// The scene does not contain any animation player.
+    AnimatorCount(usize),
+    /// The door trigger opens doors with an ID no door uses.
+    MissingDoor(u32),
+    /// The transition or prop refers to a section which has no start point.
+    MissingSectionStart(String),
+    /// The section has a start point, but no finish point.
+    MissingSectionFinish(String),
+    /// The dynamic prop node has no mesh to compute its collider from.
+    MissingPropMesh,
+    /// The dynamic prop node has no section, so it is never reset.
+    MissingPropSection,
+    /// The floor button node has no mass threshold.
+    MissingMassThreshold,
+    /// The glTF file contains no scene to spawn.
+    MissingScene,
+    /// The portal link is not used by exactly two nodes.
+    UnpairedPortal { link: String, count: usize },
+    /// The signal is read, but no node drives it.
+    MissingSignal(String),
+    /// The door shares its animation player with a door of another ID, so their animations
+    /// interrupt each other.
+    SharedDoorAnimator { door: u32, other_door: u32 },
+}
+
+/// A problem found in a level, along with the node and extra key it originates from.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LevelValidationError {
+    pub node: String,
+    pub key: Option<String>,
+    pub kind: LevelValidationErrorKind,
+}
+
+impl fmt::Display for LevelValidationError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &self.key {
+            Some(key) => write!(f, "node \"{}\", extra \"{}\": ", self.node, key)?,
+            None => write!(f, "node \"{}\": ", self.node)?,
+        }
+        match &self.kind {
+            LevelValidationErrorKind::InvalidValue(reason) => {
+                write!(f, "invalid value ({})", reason)
+            }
+            LevelValidationErrorKind::MissingAnimation(animation) => {
+                write!(f, "missing animation {}", animation)
+            }
+            LevelValidationErrorKind::DuplicateId { id, other_node } => {
+                write!(f, "ID {} is already used by node \"{}\"", id, other_node)
+            }
+            LevelValidationErrorKind::AnimatorCount(count) => write!(
+                f,
+                "expected at least one animation player in the scene, found {}",
+                count
+            ),
+            LevelValidationErrorKind::MissingDoor(id) => write!(f, "no door with ID {}", id),
+            LevelValidationErrorKind::MissingSectionStart(section) => {
+                write!(f, "no start point for section {}", section)
+            }
+            LevelValidationErrorKind::MissingSectionFinish(section) => {
+                write!(f, "no finish point for section {}", section)
+            }
+            LevelValidationErrorKind::MissingPropMesh => write!(f, "dynamic prop has no mesh"),
+            LevelValidationErrorKind::MissingPropSection => {
+                write!(f, "dynamic prop has no section, it is never reset")
+            }
+            LevelValidationErrorKind::MissingMassThreshold => {
+                write!(f, "floor button has no mass threshold")
+            }
+            LevelValidationErrorKind::MissingScene => write!(f, "the glTF file has no scene"),
+            LevelValidationErrorKind::UnpairedPortal { link, count } => write!(
+                f,
+                "portal link {} is used by {} nodes instead of 2",
+                link, count
+            ),
+            LevelValidationErrorKind::MissingSignal(signal) => {
+                write!(f, "no node drives signal {}", signal)
+            }
+            LevelValidationErrorKind::SharedDoorAnimator { door, other_door } => write!(
+                f,
+                "door {} shares its animation player with door {}",
+                door, other_door
+            ),
+        }
+    }
+}
+
+impl std::error::Error for LevelValidationError {}
+
+/// Validation report of a level, produced every time the level is (re)processed.
+#[derive(Debug, Default, Clone, TypeUuid)]
+#[uuid = "5f0e7c4a-8d5b-4b8e-9a0f-2c1d6e3b7a94"]
+pub struct LevelReport {
+    pub level_name: String,
+    pub errors: Vec<LevelValidationError>,
+}
+
+impl LevelReport {
+    pub fn new(level_name: &str) -> LevelReport {
+        LevelReport {
+            level_name: level_name.to_owned(),
+            errors: Vec::new(),
+        }
+    }
+
+    pub fn is_ok(&self) -> bool {
+        self.errors.is_empty()
+    }
+
+    pub fn push(&mut self, node: &str, key: Option<&str>, kind: LevelValidationErrorKind) {
+        self.errors.push(LevelValidationError {
+            node: node.to_owned(),
+            key: key.map(str::to_owned),
+            kind,
+        });
+    }
+
+    /// Log every error of the report.
+    pub fn log(&self) {
+        for error in &self.errors {
+            warn!("Level {}: {}", self.level_name, error);
+        }
+    }
+}
+
+/// Deserialize the extras of a node, recording every key with an invalid value in the report.
+///
+/// The keys that failed to deserialize are dropped so the valid ones still apply.
+pub(crate) fn parse_extras<T: DeserializeOwned>(
+    node: &str,
+    extras: &str,
+    report: &mut LevelReport,
+) -> Option<T> {
+    let error = match serde_json::from_str::<T>(extras) {
+        Ok(parsed) => return Some(parsed),
+        Err(e) => e,
+    };
+
+    let map = match serde_json::from_str::<Value>(extras) {
+        Ok(Value::Object(map)) => map,
+        _ => {
+            report.push(
+                node,
+                None,
+                LevelValidationErrorKind::InvalidValue(error.to_string()),
+            );
+            return None;
+        }
+    };
+
+    // Find the offending keys by deserializing them one at a time.
+    let mut valid = Map::new();
+    for (key, value) in map {
+        let single = Value::Object(Map::from_iter([(key.clone(), value.clone())]));
+        match serde_json::from_value::<T>(single) {
+            Ok(_) => {
+                valid.insert(key, value);
+            }
+            Err(e) => report.push(
+                node,
+                Some(&key),
+                LevelValidationErrorKind::InvalidValue(e.to_string()),
+            ),
+        }
+    }
+
+    match serde_json::from_value::<T>(Value::Object(valid)) {
+        Ok(parsed) => Some(parsed),
+        Err(e) => {
+            report.push(
+                node,
+                None,
+                LevelValidationErrorKind::InvalidValue(e.to_string()),
+            );
+            None
+        }
+    }
+}
@@ -0,0 +1,127 @@
+//! Floor buttons, pressed while the bodies resting on them are heavy enough.
+
+use bevy::{prelude::*, reflect::FromReflect, utils::HashSet};
+use bevy_rapier3d::prelude::*;
+
+use super::asset_processor::NodeAnimators;
+
+pub struct ButtonsPlugin;
+
+impl Plugin for ButtonsPlugin {
+    fn build(&self, app: &mut App) {
+        app.register_type::<FloorButton>()
+            .add_event::<FloorButtonPressed>()
+            .add_event::<FloorButtonReleased>()
+            .add_system(weigh_floor_buttons);
+    }
+}
+
+/// Level node pressed while the total mass of the bodies on it reaches its threshold.
+#[derive(Debug, Default, Component, Reflect, FromReflect)]
+#[reflect(Component)]
+pub struct FloorButton {
+    pub id: u32,
+    /// Mass needed to press the button, in kilograms.
+    pub mass_threshold: f32,
+    pub pressed: bool,
+    pub active_collisions: HashSet<Entity>,
+    pub animation_press: Handle<AnimationClip>,
+    pub animation_release: Handle<AnimationClip>,
+}
+
+/// Sent when a floor button gets pressed.
+#[derive(Debug, Clone)]
+pub struct FloorButtonPressed {
+    pub button: Entity,
+    pub id: u32,
+}
+
+/// Sent when a floor button gets released.
+#[derive(Debug, Clone)]
+pub struct FloorButtonReleased {
+    pub button: Entity,
+    pub id: u32,
+}
+
+/// Return the mass of a collider, computed from its shape if it only has a density.
+fn collider_mass(collider: &Collider, mass_properties: Option<&ColliderMassProperties>) -> f32 {
+    match mass_properties {
+        Some(ColliderMassProperties::Mass(mass)) => *mass,
+        Some(ColliderMassProperties::MassProperties(properties)) => properties.mass,
+        Some(ColliderMassProperties::Density(density)) => {
+            collider.raw.mass_properties(*density).mass()
+        }
+        None => collider.raw.mass_properties(1.).mass(),
+    }
+}
+
+/// Track the bodies on the floor buttons, and press or release them when their total mass crosses
+/// the threshold.
+fn weigh_floor_buttons(
+    mut animators: NodeAnimators,
+    mut collisions: EventReader<CollisionEvent>,
+    mut buttons_query: Query<(&mut FloorButton, Entity)>,
+    bodies_query: Query<(&Collider, Option<&ColliderMassProperties>)>,
+    mut pressed_events: EventWriter<FloorButtonPressed>,
+    mut released_events: EventWriter<FloorButtonReleased>,
+) {
+    let mut changed_buttons = HashSet::new();
+    for collision in collisions.iter() {
+        let (collider_a, collider_b, started) = match collision {
+            CollisionEvent::Started(collider_a, collider_b, _flags) => {
+                (collider_a, collider_b, true)
+            }
+            CollisionEvent::Stopped(collider_a, collider_b, _flags) => {
+                (collider_a, collider_b, false)
+            }
+        };
+        let (button_entity, cause) = if buttons_query.contains(*collider_a) {
+            (*collider_a, *collider_b)
+        } else if buttons_query.contains(*collider_b) {
+            (*collider_b, *collider_a)
+        } else {
+            continue;
+        };
+        let (mut button, _) = buttons_query.get_mut(button_entity).unwrap();
+        if started {
+            button.active_collisions.insert(cause);
+        } else {
+            button.active_collisions.remove(&cause);
+        }
+        changed_buttons.insert(button_entity);
+    }
+
+    for button_entity in changed_buttons {
+        let (mut button, _) = buttons_query.get_mut(button_entity).unwrap();
+        let mass = button
+            .active_collisions
+            .iter()
+            .filter_map(|entity| bodies_query.get(*entity).ok())
+            .map(|(collider, mass_properties)| collider_mass(collider, mass_properties))
+            .sum::<f32>();
+        let pressed = !button.active_collisions.is_empty() && mass >= button.mass_threshold;
+        if button.pressed == pressed {
+            continue;
+        }
+        button.pressed = pressed;
+        info!(
+            "Floor button {} {} with {} kg",
+            button.id,
+            if pressed { "pressed" } else { "released" },
+            mass
+        );
+        if pressed {
+            animators.play(button_entity, button.animation_press.clone());
+            pressed_events.send(FloorButtonPressed {
+                button: button_entity,
+                id: button.id,
+            });
+        } else {
+            animators.play(button_entity, button.animation_release.clone());
+            released_events.send(FloorButtonReleased {
+                button: button_entity,
+                id: button.id,
+            });
+        }
+    }
+}
@@ -0,0 +1,564 @@
+//! Checkpoints and respawning: the player respawns at the last checkpoint it went through in the
+//! current section, or at the start of the section, when it enters a kill volume or falls out of
+//! the world.
+//!
+//! Respawning closes the player portals and returns the props of the section to their authored
+//! transform. Resetting the section also despawns the thrown cubes, closes the doors of the section
+//! and gives back the pickups collected since the player entered it.
+
+use bevy::{ecs::system::SystemParam, prelude::*, reflect::FromReflect, utils::HashSet};
+use bevy_rapier3d::prelude::*;
+use iyes_loopless::prelude::*;
+use leafwing_input_manager::prelude::ActionState;
+
+use super::{
+    asset_processor::{CurrentLevel, LevelProp, NodeSection, SectionStart},
+    doors::Door,
+    first_person_controller::{release_object, FirstPersonController},
+    game::{
+        restore_pickup_sensor, CollectedPickups, ConsumedPickupSensor, GameState, Pickup,
+        PlayerProgress, ThrownCube,
+    },
+    input::Actions,
+    portal::{
+        close_player_portals, restore_dissolved_prop, Dissolved, Dissolving, Portal, PortalTeleport,
+    },
+    signals::{LogicGate, SignalSource},
+};
+
+pub struct CheckpointsPlugin;
+
+impl Plugin for CheckpointsPlugin {
+    fn build(&self, app: &mut App) {
+        app.register_type::<Checkpoint>()
+            .register_type::<KillVolume>()
+            .register_type::<VoidPlane>()
+            .init_resource::<VoidPlane>()
+            .init_resource::<ActiveCheckpoint>()
+            .init_resource::<SectionSnapshot>()
+            .add_event::<PlayerKilled>()
+            .add_event::<ResetSection>()
+            .add_system(snapshot_section.run_in_state(GameState::InGame))
+            .add_system(activate_checkpoints.run_in_state(GameState::InGame))
+            .add_system(
+                kill_in_volumes
+                    .run_in_state(GameState::InGame)
+                    .label(CheckpointLabels::Kill),
+            )
+            .add_system(
+                kill_out_of_world
+                    .run_in_state(GameState::InGame)
+                    .label(CheckpointLabels::Kill),
+            )
+            .add_system(
+                respawn_player
+                    .run_in_state(GameState::InGame)
+                    .after(CheckpointLabels::Kill),
+            )
+            .add_system(
+                request_section_reset
+                    .run_in_state(GameState::InGame)
+                    .label(CheckpointLabels::RequestReset),
+            )
+            .add_system(
+                reset_section
+                    .run_in_state(GameState::InGame)
+                    .after(CheckpointLabels::RequestReset),
+            );
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash, SystemLabel)]
+pub enum CheckpointLabels {
+    Kill,
+    RequestReset,
+}
+
+/// Default height of the [`VoidPlane`].
+const DEFAULT_VOID_HEIGHT: f32 = -50.;
+
+/// Level node the player respawns at once it went through its volume.
+#[derive(Debug, Default, Component, Reflect, FromReflect)]
+#[reflect(Component)]
+pub struct Checkpoint;
+
+/// Level volume killing the player.
+#[derive(Debug, Default, Component, Reflect, FromReflect)]
+#[reflect(Component)]
+pub struct KillVolume;
+
+/// Height under which the player and the props fall out of the world.
+#[derive(Debug, Reflect, Resource)]
+pub struct VoidPlane {
+    pub height: f32,
+}
+
+impl Default for VoidPlane {
+    fn default() -> Self {
+        VoidPlane {
+            height: DEFAULT_VOID_HEIGHT,
+        }
+    }
+}
+
+/// Last checkpoint the player went through, and the section it was reached in.
+#[derive(Debug, Default, Resource)]
+pub struct ActiveCheckpoint {
+    pub checkpoint: Option<Entity>,
+    pub section: String,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum KillCause {
+    KillVolume,
+    Void,
+}
+
+/// Sent when the player gets killed, it respawns in the same frame.
+#[derive(Debug, Clone)]
+pub struct PlayerKilled {
+    pub cause: KillCause,
+}
+
+/// Sent to reset a section of the current level.
+#[derive(Debug, Clone)]
+pub struct ResetSection {
+    pub section: String,
+}
+
+/// Progress of the player when it entered the current section, restored by a section reset.
+#[derive(Debug, Default, Resource)]
+pub struct SectionSnapshot {
+    pub section: String,
+    pub progress: PlayerProgress,
+    pub collected: HashSet<u32>,
+}
+
+/// The level props, reset along with their section.
+#[derive(SystemParam)]
+pub struct SectionProps<'w, 's> {
+    props_query: Query<
+        'w,
+        's,
+        (
+            &'static LevelProp,
+            Option<&'static NodeSection>,
+            &'static mut Transform,
+            &'static mut Velocity,
+        ),
+        (With<PortalTeleport>, Without<FirstPersonController>),
+    >,
+    dissolved_props_query: Query<
+        'w,
+        's,
+        (&'static LevelProp, Option<&'static NodeSection>, Entity),
+        Or<(With<Dissolving>, With<Dissolved>)>,
+    >,
+    global_transform_query: Query<'w, 's, &'static GlobalTransform>,
+}
+
+impl<'w, 's> SectionProps<'w, 's> {
+    /// Return the props of the section to their authored transform, bringing back the dissolved
+    /// ones, and drop the object carried by the player.
+    pub fn reset(
+        &mut self,
+        commands: &mut Commands,
+        section: &str,
+        controller: &mut FirstPersonController,
+    ) {
+        if let Some(grabbed_object) = controller.grabbed_object.take() {
+            let object_transform = match self.props_query.get(grabbed_object) {
+                Ok((level_prop, node_section, _, _))
+                    if NodeSection::contains(node_section, section) =>
+                {
+                    level_prop.authored_transform
+                }
+                _ => self
+                    .global_transform_query
+                    .get(grabbed_object)
+                    .map(GlobalTransform::compute_transform)
+                    .unwrap_or_default(),
+            };
+            release_object(commands, grabbed_object, object_transform);
+        }
+        for (level_prop, node_section, mut transform, mut velocity) in &mut self.props_query {
+            if NodeSection::contains(node_section, section) {
+                *transform = level_prop.authored_transform;
+                *velocity = Velocity::default();
+            }
+        }
+        for (level_prop, node_section, entity) in &self.dissolved_props_query {
+            if NodeSection::contains(node_section, section) {
+                restore_dissolved_prop(commands, entity, level_prop.authored_transform);
+            }
+        }
+    }
+}
+
+fn activate_checkpoints(
+    mut collisions: EventReader<CollisionEvent>,
+    checkpoints_query: Query<(), With<Checkpoint>>,
+    player_query: Query<(), With<FirstPersonController>>,
+    current_level: Res<CurrentLevel>,
+    mut active_checkpoint: ResMut<ActiveCheckpoint>,
+) {
+    for collision in collisions.iter() {
+        let (collider_a, collider_b) = match collision {
+            CollisionEvent::Started(collider_a, collider_b, _flags) => (collider_a, collider_b),
+            CollisionEvent::Stopped(_collider_a, _collider_b, _flags) => continue,
+        };
+        let (checkpoint, other) = if checkpoints_query.contains(*collider_a) {
+            (*collider_a, *collider_b)
+        } else if checkpoints_query.contains(*collider_b) {
+            (*collider_b, *collider_a)
+        } else {
+            continue;
+        };
+        if !player_query.contains(other) || active_checkpoint.checkpoint == Some(checkpoint) {
+            continue;
+        }
+        info!("Checkpoint {:?} reached", checkpoint);
+        *active_checkpoint = ActiveCheckpoint {
+            checkpoint: Some(checkpoint),
+            section: current_level.current_section(),
+        };
+    }
+}
+
+/// Return a prop lost by the player to its authored transform, thrown cubes are despawned.
+fn recover_prop(
+    commands: &mut Commands,
+    entity: Entity,
+    mut transform: Mut<Transform>,
+    mut velocity: Mut<Velocity>,
+    level_prop: Option<&LevelProp>,
+) {
+    match level_prop {
+        Some(level_prop) => {
+            *transform = level_prop.authored_transform;
+            *velocity = Velocity::default();
+        }
+        None => commands.entity(entity).despawn_recursive(),
+    }
+}
+
+/// Kill the player when it enters a kill volume, and recover the props entering one.
+fn kill_in_volumes(
+    mut commands: Commands,
+    mut collisions: EventReader<CollisionEvent>,
+    kill_volumes_query: Query<(), With<KillVolume>>,
+    player_query: Query<(), With<FirstPersonController>>,
+    mut props_query: Query<
+        (&mut Transform, &mut Velocity, Option<&LevelProp>),
+        (With<PortalTeleport>, Without<FirstPersonController>),
+    >,
+    mut killed: EventWriter<PlayerKilled>,
+) {
+    for collision in collisions.iter() {
+        let (collider_a, collider_b) = match collision {
+            CollisionEvent::Started(collider_a, collider_b, _flags) => (collider_a, collider_b),
+            CollisionEvent::Stopped(_collider_a, _collider_b, _flags) => continue,
+        };
+        let other = if kill_volumes_query.contains(*collider_a) {
+            *collider_b
+        } else if kill_volumes_query.contains(*collider_b) {
+            *collider_a
+        } else {
+            continue;
+        };
+        if player_query.contains(other) {
+            killed.send(PlayerKilled {
+                cause: KillCause::KillVolume,
+            });
+        } else if let Ok((transform, velocity, level_prop)) = props_query.get_mut(other) {
+            info!("Prop {:?} entered a kill volume", other);
+            recover_prop(&mut commands, other, transform, velocity, level_prop);
+        }
+    }
+}
+
+/// Kill the player when it falls under the void plane, and recover the props falling under it.
+fn kill_out_of_world(
+    mut commands: Commands,
+    player_query: Query<&GlobalTransform, With<FirstPersonController>>,
+    mut props_query: Query<
+        (
+            &GlobalTransform,
+            &RigidBody,
+            &mut Transform,
+            &mut Velocity,
+            Option<&LevelProp>,
+            Entity,
+        ),
+        (With<PortalTeleport>, Without<FirstPersonController>),
+    >,
+    void: Res<VoidPlane>,
+    mut killed: EventWriter<PlayerKilled>,
+) {
+    if let Ok(player_transform) = player_query.get_single() {
+        if player_transform.translation().y < void.height {
+            killed.send(PlayerKilled {
+                cause: KillCause::Void,
+            });
+        }
+    }
+    for (global_transform, rigidbody, transform, velocity, level_prop, entity) in &mut props_query {
+        // The carried props fall along with the player.
+        if *rigidbody == RigidBody::Dynamic && global_transform.translation().y < void.height {
+            info!("Prop {:?} fell out of the world", entity);
+            recover_prop(&mut commands, entity, transform, velocity, level_prop);
+        }
+    }
+}
+
+/// Respawn the killed player at the active checkpoint of the current section, or at the start of
+/// the section, then close the player portals and reset the props of the section.
+#[allow(clippy::too_many_arguments)]
+fn respawn_player(
+    mut commands: Commands,
+    mut killed: EventReader<PlayerKilled>,
+    mut player_query: Query<(&mut Transform, &mut Velocity, &mut FirstPersonController)>,
+    mut props: SectionProps,
+    spawn_points_query: Query<
+        (Option<&SectionStart>, &GlobalTransform),
+        Or<(With<Checkpoint>, With<SectionStart>)>,
+    >,
+    portal_query: Query<(&Portal, Entity)>,
+    active_checkpoint: Res<ActiveCheckpoint>,
+    current_level: Res<CurrentLevel>,
+) {
+    let cause = match killed.iter().last() {
+        Some(killed) => killed.cause,
+        None => return,
+    };
+    let section = current_level.current_section();
+    let spawn_point = match find_spawn_point(&active_checkpoint, &section, &spawn_points_query) {
+        Some(spawn_point) => spawn_point,
+        None => {
+            warn!("No spawn point for section {}", section);
+            return;
+        }
+    };
+    let (mut transform, mut velocity, mut controller) = match player_query.get_single_mut() {
+        Ok(player) => player,
+        Err(_) => return,
+    };
+    info!(
+        "Player killed by {:?}, respawning in section {}",
+        cause, section
+    );
+    transform.translation = spawn_point.translation;
+    transform.rotation = spawn_point.rotation;
+    *velocity = Velocity::default();
+
+    close_player_portals(&mut commands, &portal_query);
+    props.reset(&mut commands, &section, &mut controller);
+}
+
+/// Return where the player respawns in the section: at the active checkpoint if it was reached in
+/// this section, or else at the start of the section.
+fn find_spawn_point(
+    active_checkpoint: &ActiveCheckpoint,
+    section: &str,
+    spawn_points_query: &Query<
+        (Option<&SectionStart>, &GlobalTransform),
+        Or<(With<Checkpoint>, With<SectionStart>)>,
+    >,
+) -> Option<Transform> {
+    active_checkpoint
+        .checkpoint
+        .filter(|_| active_checkpoint.section == section)
+        .and_then(|checkpoint| spawn_points_query.get(checkpoint).ok())
+        .or_else(|| {
+            spawn_points_query
+                .iter()
+                .find(|(start, _)| start.map_or(false, |start| start.section_name == section))
+        })
+        .map(|(_, spawn_transform)| spawn_transform.compute_transform())
+}
+
+/// Record the progress of the player whenever it enters a section.
+fn snapshot_section(
+    mut snapshot: ResMut<SectionSnapshot>,
+    current_level: Res<CurrentLevel>,
+    progress: Res<PlayerProgress>,
+    collected: Res<CollectedPickups>,
+) {
+    if current_level.is_changed() {
+        *snapshot = SectionSnapshot {
+            section: current_level.current_section(),
+            progress: progress.clone(),
+            collected: collected.ids.clone(),
+        };
+    }
+}
+
+fn request_section_reset(
+    input_query: Query<&ActionState<Actions>, With<FirstPersonController>>,
+    current_level: Res<CurrentLevel>,
+    mut resets: EventWriter<ResetSection>,
+) {
+    if input_query
+        .get_single()
+        .map_or(false, |input| input.just_pressed(Actions::ResetSection))
+    {
+        resets.send(ResetSection {
+            section: current_level.current_section(),
+        });
+    }
+}
+
+/// Reset a section: despawn the thrown cubes, reset the props, doors and logic gates of the
+/// section, and give back the pickups collected since the player entered it.
+#[allow(clippy::too_many_arguments)]
+fn reset_section(
+    mut commands: Commands,
+    mut resets: EventReader<ResetSection>,
+    mut player_query: Query<&mut FirstPersonController>,
+    mut props: SectionProps,
+    cubes_query: Query<Entity, With<ThrownCube>>,
+    mut doors_query: Query<(&mut Door, Option<&NodeSection>)>,
+    mut gates_query: Query<(&mut LogicGate, &mut SignalSource, Option<&NodeSection>)>,
+    mut pickups_query: Query<(&Pickup, &mut Visibility)>,
+    mut consumed_sensors_query: Query<
+        (&ConsumedPickupSensor, Option<&mut SignalSource>, Entity),
+        Without<LogicGate>,
+    >,
+    snapshot: Res<SectionSnapshot>,
+    mut progress: ResMut<PlayerProgress>,
+    mut collected: ResMut<CollectedPickups>,
+) {
+    let section = match resets.iter().last() {
+        Some(reset) => reset.section.clone(),
+        None => return,
+    };
+    info!("Resetting section {}", section);
+
+    if let Ok(mut controller) = player_query.get_single_mut() {
+        props.reset(&mut commands, &section, &mut controller);
+    }
+    for entity in &cubes_query {
+        commands.entity(entity).despawn_recursive();
+    }
+
+    // Doors and gates without a section may have been unlocked in an earlier section, and are kept.
+    for (mut door, node_section) in &mut doors_query {
+        if NodeSection::contains(node_section, &section) && door.open {
+            door.open = false;
+        }
+    }
+    for (mut gate, mut source, node_section) in &mut gates_query {
+        if !NodeSection::contains(node_section, &section) {
+            continue;
+        }
+        gate.remaining = 0.;
+        if source.high {
+            source.high = false;
+        }
+    }
+
+    // The pickups collected before entering the section are kept.
+    if snapshot.section != section {
+        return;
+    }
+    let given_back = collected
+        .ids
+        .difference(&snapshot.collected)
+        .copied()
+        .collect::<HashSet<_>>();
+    for (consumed, source, entity) in &mut consumed_sensors_query {
+        if given_back.contains(&consumed.sensor.pickup_id) {
+            restore_pickup_sensor(&mut commands, entity, consumed.sensor.clone(), source);
+        }
+    }
+    for (pickup, mut visibility) in &mut pickups_query {
+        if given_back.contains(&pickup.id) {
+            visibility.is_visible = true;
+        }
+    }
+    collected.ids = snapshot.collected.clone();
+    *progress = snapshot.progress.clone();
+}
+
+#[cfg(test)]
+mod tests {
+    use bevy::ecs::system::SystemState;
+
+    use super::*;
+
+    type SpawnPointsState = SystemState<
+        Query<
+            'static,
+            'static,
+            (Option<&'static SectionStart>, &'static GlobalTransform),
+            Or<(With<Checkpoint>, With<SectionStart>)>,
+        >,
+    >;
+
+    fn spawn_section_start(world: &mut World, section: &str, translation: Vec3) {
+        world.spawn((
+            SectionStart {
+                section_name: section.to_owned(),
+            },
+            GlobalTransform::from_translation(translation),
+        ));
+    }
+
+    /// World with the start points of two sections and a checkpoint.
+    fn spawn_points_world() -> (World, Entity) {
+        let mut world = World::new();
+        spawn_section_start(&mut world, "first", Vec3::X);
+        spawn_section_start(&mut world, "second", Vec3::Y);
+        let checkpoint = world
+            .spawn((Checkpoint, GlobalTransform::from_translation(Vec3::Z)))
+            .id();
+        (world, checkpoint)
+    }
+
+    fn spawn_translation(
+        world: &mut World,
+        active_checkpoint: &ActiveCheckpoint,
+        section: &str,
+    ) -> Option<Vec3> {
+        let mut state = SpawnPointsState::new(world);
+        let spawn_points_query = state.get(world);
+        find_spawn_point(active_checkpoint, section, &spawn_points_query)
+            .map(|spawn_point| spawn_point.translation)
+    }
+
+    #[test]
+    fn respawns_at_checkpoint_of_current_section() {
+        let (mut world, checkpoint) = spawn_points_world();
+        let active_checkpoint = ActiveCheckpoint {
+            checkpoint: Some(checkpoint),
+            section: "second".to_owned(),
+        };
+        let spawn = spawn_translation(&mut world, &active_checkpoint, "second");
+        assert_eq!(spawn, Some(Vec3::Z));
+    }
+
+    #[test]
+    fn ignores_checkpoint_of_other_section() {
+        let (mut world, checkpoint) = spawn_points_world();
+        let active_checkpoint = ActiveCheckpoint {
+            checkpoint: Some(checkpoint),
+            section: "first".to_owned(),
+        };
+        let spawn = spawn_translation(&mut world, &active_checkpoint, "second");
+        assert_eq!(spawn, Some(Vec3::Y));
+    }
+
+    #[test]
+    fn respawns_at_section_start_without_checkpoint() {
+        let (mut world, _checkpoint) = spawn_points_world();
+        let spawn = spawn_translation(&mut world, &ActiveCheckpoint::default(), "first");
+        assert_eq!(spawn, Some(Vec3::X));
+    }
+
+    #[test]
+    fn no_spawn_point_for_unknown_section() {
+        let (mut world, _checkpoint) = spawn_points_world();
+        let spawn = spawn_translation(&mut world, &ActiveCheckpoint::default(), "third");
+        assert_eq!(spawn, None);
+    }
+}
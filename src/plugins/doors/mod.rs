@@ -2,7 +2,7 @@ use bevy::{prelude::*, reflect::FromReflect, utils::HashSet};
 use bevy_rapier3d::prelude::*;
 use serde::Deserialize;
 
-use super::asset_processor::SceneAnimationPlayer;
+use super::asset_processor::NodeAnimators;
 
 pub struct DoorsPlugin;
 
@@ -11,10 +11,20 @@ impl Plugin for DoorsPlugin {
         app.register_type::<Door>()
             .register_type::<DoorSensor>()
             .register_type::<DoorSidedness>()
-            .add_system(open_doors_on_sensor_activation);
+            .register_type::<DoorAnimations>()
+            .add_event::<DoorStateChanged>()
+            .add_system(open_doors_on_sensor_activation.before(DoorLabels::Animate))
+            .add_system(animate_doors.label(DoorLabels::Animate));
     }
 }
 
+#[derive(Debug, Clone, PartialEq, Eq, Hash, SystemLabel)]
+pub enum DoorLabels {
+    /// Play the animations of the doors which were opened or closed.
+    Animate,
+}
+
+/// The side the door swings open towards.
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Deserialize, Reflect, FromReflect)]
 pub enum DoorSidedness {
     #[default]
@@ -22,14 +32,55 @@ pub enum DoorSidedness {
     Right,
 }
 
+/// Level node opened and closed by its sensors, or by a signal.
+///
+/// The other systems only set [`Door::open`], the doors are animated once their state changes.
 #[derive(Debug, Default, Component, Reflect, FromReflect)]
 #[reflect(Component)]
 pub struct Door {
     pub id: u32,
     pub sidedness: DoorSidedness,
     pub open: bool,
-    pub animation_open: Handle<AnimationClip>,
-    pub animation_close: Handle<AnimationClip>,
+    /// Locked doors ignore their sensors.
+    pub locked: bool,
+    /// Signal unlocking the door while it is high, if the door is locked.
+    pub unlock_signal: Option<String>,
+    /// Duration the door stays open before closing on its own, in seconds.
+    pub stay_open_for: Option<f32>,
+    pub animations: DoorAnimations,
+    /// Whether the open animation was the last one played.
+    animated_open: bool,
+    close_timer: Timer,
+}
+
+impl Door {
+    pub fn animation_open(&self) -> Handle<AnimationClip> {
+        match self.sidedness {
+            DoorSidedness::Left => self.animations.open_left.clone(),
+            DoorSidedness::Right => self.animations.open_right.clone(),
+        }
+    }
+
+    pub fn animation_close(&self) -> Handle<AnimationClip> {
+        match self.sidedness {
+            DoorSidedness::Left => self.animations.close_left.clone(),
+            DoorSidedness::Right => self.animations.close_right.clone(),
+        }
+    }
+
+    /// Open or close the door from a sensor.
+    ///
+    /// Locked doors stay as they are, and the doors closing on their own ignore close requests but
+    /// stay open longer when opened again.
+    pub fn trigger(&mut self, open: bool) {
+        if self.locked || (!open && self.stay_open_for.is_some()) {
+            return;
+        }
+        if open {
+            self.close_timer.reset();
+        }
+        self.open = open;
+    }
 }
 
 #[derive(Debug, Default, Component, Reflect, FromReflect)]
@@ -40,7 +91,7 @@ pub struct DoorSensor {
     pub active_collisions: HashSet<Entity>,
 }
 
-#[derive(Debug, Default)]
+#[derive(Debug, Default, Clone, Reflect, FromReflect)]
 pub struct DoorAnimations {
     pub close_left: Handle<AnimationClip>,
     pub close_right: Handle<AnimationClip>,
@@ -48,66 +99,65 @@ pub struct DoorAnimations {
     pub open_right: Handle<AnimationClip>,
 }
 
+/// Sent when a door starts opening or closing.
+#[derive(Debug, Clone)]
+pub struct DoorStateChanged {
+    pub door: Entity,
+    pub id: u32,
+    pub open: bool,
+}
+
 fn open_doors_on_sensor_activation(
-    mut animator_query: Query<Option<&mut AnimationPlayer>, With<SceneAnimationPlayer>>,
     mut doors_query: Query<&mut Door>,
     mut collisions: EventReader<CollisionEvent>,
     mut sensor_query: Query<(&mut DoorSensor, Entity), Without<Door>>,
 ) {
-    if let Ok(Some(mut animator)) = animator_query.get_single_mut() {
-        for collision in collisions.iter() {
-            match collision {
-                CollisionEvent::Started(collider_a, collider_b, _flags) => {
-                    let maybe_sensor_entity = sensor_query
-                        .get(*collider_a)
-                        .or_else(|_| sensor_query.get(*collider_b))
-                        .map(|r| r.1);
-                    if let Ok(sensor_entity) = maybe_sensor_entity {
-                        let (mut sensor, sensor_entity) =
-                            sensor_query.get_mut(sensor_entity).unwrap();
-                        let cause = if &sensor_entity == collider_a {
-                            *collider_b
-                        } else {
-                            *collider_a
-                        };
-                        if sensor.active_collisions.is_empty() {
-                            info!(
-                                "Sensor for door {} activated, opening door entities {:?}",
-                                sensor.doors_id, &sensor.door_entities
-                            );
-                            for entity in &sensor.door_entities {
-                                let mut door = doors_query.get_mut(*entity).unwrap();
-                                animator.play(door.animation_open.clone());
-                                door.open = true;
-                            }
+    for collision in collisions.iter() {
+        match collision {
+            CollisionEvent::Started(collider_a, collider_b, _flags) => {
+                let maybe_sensor_entity = sensor_query
+                    .get(*collider_a)
+                    .or_else(|_| sensor_query.get(*collider_b))
+                    .map(|r| r.1);
+                if let Ok(sensor_entity) = maybe_sensor_entity {
+                    let (mut sensor, sensor_entity) = sensor_query.get_mut(sensor_entity).unwrap();
+                    let cause = if &sensor_entity == collider_a {
+                        *collider_b
+                    } else {
+                        *collider_a
+                    };
+                    if sensor.active_collisions.is_empty() {
+                        info!(
+                            "Sensor for door {} activated, opening door entities {:?}",
+                            sensor.doors_id, &sensor.door_entities
+                        );
+                        for entity in &sensor.door_entities {
+                            doors_query.get_mut(*entity).unwrap().trigger(true);
                         }
-                        sensor.active_collisions.insert(cause);
                     }
+                    sensor.active_collisions.insert(cause);
                 }
-                CollisionEvent::Stopped(collider_a, collider_b, _flags) => {
-                    let maybe_sensor_entity = sensor_query
-                        .get(*collider_a)
-                        .or_else(|_| sensor_query.get(*collider_b))
-                        .map(|r| r.1);
-                    if let Ok(sensor_entity) = maybe_sensor_entity {
-                        let (mut sensor, sensor_entity) =
-                            sensor_query.get_mut(sensor_entity).unwrap();
-                        let cause = if &sensor_entity == collider_a {
-                            *collider_b
-                        } else {
-                            *collider_a
-                        };
-                        sensor.active_collisions.remove(&cause);
-                        if sensor.active_collisions.is_empty() {
-                            info!(
-                                "Sensor for door {} deactivated, closin door entities {:?}",
-                                sensor.doors_id, &sensor.door_entities
-                            );
-                            for entity in &sensor.door_entities {
-                                let mut door = doors_query.get_mut(*entity).unwrap();
-                                animator.play(door.animation_close.clone());
-                                door.open = false;
-                            }
+            }
+            CollisionEvent::Stopped(collider_a, collider_b, _flags) => {
+                let maybe_sensor_entity = sensor_query
+                    .get(*collider_a)
+                    .or_else(|_| sensor_query.get(*collider_b))
+                    .map(|r| r.1);
+                if let Ok(sensor_entity) = maybe_sensor_entity {
+                    let (mut sensor, sensor_entity) = sensor_query.get_mut(sensor_entity).unwrap();
+                    let cause = if &sensor_entity == collider_a {
+                        *collider_b
+                    } else {
+                        *collider_a
+                    };
+                    sensor.active_collisions.remove(&cause);
+                    if sensor.active_collisions.is_empty() {
+                        info!(
+                            "Sensor for door {} deactivated, closin door entities {:?}",
+                            sensor.doors_id, &sensor.door_entities
+                        );
+                        for entity in &sensor.door_entities {
+                            doors_query.get_mut(*entity).unwrap().trigger(false);
                         }
                     }
                 }
@@ -115,3 +165,107 @@ fn open_doors_on_sensor_activation(
         }
     }
 }
+
+/// Play the animations of the doors which were opened or closed, and close the doors which stayed
+/// open long enough.
+fn animate_doors(
+    mut animators: NodeAnimators,
+    mut doors_query: Query<(&mut Door, Entity)>,
+    mut door_events: EventWriter<DoorStateChanged>,
+    time: Res<Time>,
+) {
+    for (mut door, entity) in &mut doors_query {
+        // The close timer starts along with the open animation.
+        if door.open && door.animated_open && door.stay_open_for.is_some() {
+            door.close_timer.tick(time.delta());
+            if door.close_timer.just_finished() {
+                info!("Door {} closing on its own", door.id);
+                door.open = false;
+            }
+        }
+        if door.open == door.animated_open {
+            continue;
+        }
+        door.animated_open = door.open;
+        if door.open {
+            if let Some(stay_open_for) = door.stay_open_for {
+                door.close_timer = Timer::from_seconds(stay_open_for, TimerMode::Once);
+            }
+            animators.play(entity, door.animation_open());
+        } else {
+            animators.play(entity, door.animation_close());
+        }
+        door_events.send(DoorStateChanged {
+            door: entity,
+            id: door.id,
+            open: door.open,
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use bevy::utils::{Duration, Instant};
+
+    use super::*;
+
+    fn doors_app() -> App {
+        let mut app = App::new();
+        app.init_resource::<Time>()
+            .add_event::<DoorStateChanged>()
+            .add_system(animate_doors);
+        app
+    }
+
+    /// Run a frame of the app at the given time since the start.
+    fn run_frame(app: &mut App, start: Instant, seconds: f32) {
+        app.world
+            .resource_mut::<Time>()
+            .update_with_instant(start + Duration::from_secs_f32(seconds));
+        app.update();
+    }
+
+    #[test]
+    fn timed_door_stays_open_then_closes() {
+        let mut app = doors_app();
+        let mut door = Door {
+            id: 1,
+            stay_open_for: Some(1.),
+            ..default()
+        };
+        door.trigger(true);
+        let entity = app.world.spawn(door).id();
+
+        let start = Instant::now();
+        run_frame(&mut app, start, 0.);
+        let events = app.world.resource::<Events<DoorStateChanged>>();
+        let opened = events.get_reader().iter(events).any(|event| event.open);
+        assert!(opened);
+        run_frame(&mut app, start, 0.016);
+        assert!(app.world.get::<Door>(entity).unwrap().open);
+
+        run_frame(&mut app, start, 1.5);
+        assert!(!app.world.get::<Door>(entity).unwrap().open);
+    }
+
+    #[test]
+    fn timed_door_ignores_close_requests() {
+        let mut door = Door {
+            stay_open_for: Some(1.),
+            ..default()
+        };
+        door.trigger(true);
+        door.trigger(false);
+        assert!(door.open);
+    }
+
+    #[test]
+    fn locked_door_ignores_sensors() {
+        let mut door = Door {
+            locked: true,
+            ..default()
+        };
+        door.trigger(true);
+        assert!(!door.open);
+    }
+}
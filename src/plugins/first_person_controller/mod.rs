@@ -19,7 +19,11 @@ use euclid::Angle;
 use iyes_loopless::condition::IntoConditionalSystem;
 use leafwing_input_manager::prelude::*;
 
-use crate::plugins::{input::default_input_map, physics::*, portal::PortalTeleport};
+use crate::plugins::{
+    input::default_input_map,
+    physics::*,
+    portal::{PortalRaycast, PortalTeleport},
+};
 
 use super::{
     asset_processor::{CurrentLevel, Level},
@@ -165,7 +169,6 @@ fn spawn_controller(
             .spawn(Camera3dBundle {
                 projection: Projection::Perspective(PerspectiveProjection {
                     fov: std::f32::consts::FRAC_PI_4,
-                    // TODO: make the portal cameras use the main camera FOV so we can change this
                     aspect_ratio: 16. / 9.,
                     near: 0.1,
                     far: 1000.,
@@ -227,7 +230,7 @@ fn process_controller_inputs(
         ),
         (Without<FirstPersonController>, Without<CameraAnchor>),
     >,
-    rapier: Res<RapierContext>,
+    raycast: PortalRaycast,
 ) {
     for (input_state, mut controller, mut velocity, transform, yaw_lock, player_entity) in
         &mut player_query
@@ -322,7 +325,8 @@ fn process_controller_inputs(
                         cam_global_transform.translation(),
                         cam_global_transform.forward()
                     );
-                    if let Some((entity, distance)) = rapier.cast_ray(
+                    // Props can be grabbed through the portals.
+                    let ray = raycast.cast_ray(
                         cam_global_transform.translation(),
                         cam_global_transform.forward(),
                         1.5,
@@ -331,7 +335,15 @@ fn process_controller_inputs(
                             RAYCAST_GROUP.bits().into(),
                             PROPS_GROUP.bits().into(),
                         )),
-                    ) {
+                    );
+                    if let Some((entity, distance)) = ray.hit.map(|(entity, _)| {
+                        let distance = ray
+                            .segments
+                            .iter()
+                            .map(|segment| segment.length)
+                            .sum::<f32>();
+                        (entity, distance)
+                    }) {
                         let (
                             prop_name,
                             _prop_global_transform,
@@ -375,12 +387,22 @@ fn process_controller_inputs(
     }
 }
 
+/// Drop an object carried by the player at the given transform, making it dynamic again.
+pub fn release_object(commands: &mut Commands, object: Entity, transform: Transform) {
+    commands.entity(object).remove_parent().insert((
+        transform,
+        Velocity::default(),
+        RigidBody::Dynamic,
+        CollisionGroups::new(PROPS_GROUP, ALL_GROUPS),
+    ));
+}
+
 fn show_gun_on_pickup(
     mut visibility_query: Query<&mut Visibility>,
     player_query: Query<&FirstPersonController>,
     progress: Res<PlayerProgress>,
 ) {
-    if *progress != PlayerProgress::GettingStarted {
+    if progress.has_portal_gun() {
         for controller in &player_query {
             if let Ok(mut visibility) = visibility_query.get_mut(controller.weapon_node) {
                 visibility.is_visible = true;
@@ -1,16 +1,18 @@
 use crate::plugins::*;
 
-use bevy::{log::LogPlugin, prelude::*, reflect::FromReflect};
+use bevy::{log::LogPlugin, prelude::*, reflect::FromReflect, utils::HashSet};
 use bevy_rapier3d::prelude::*;
 use iyes_loopless::prelude::{AppLooplessStateExt, IntoConditionalSystem};
 use leafwing_input_manager::prelude::ActionState;
+use serde::{Deserialize, Serialize};
 
 use super::{
-    asset_processor::{Level, LevelProcessor},
+    asset_processor::{Level, LevelProcessor, LEVEL_MANIFEST_FILE},
     first_person_controller::{FirstPersonCamera, FirstPersonController},
     input::Actions,
     physics::*,
     portal::PortalTeleport,
+    signals::SignalSource,
 };
 
 /// The different possible states of the game application.
@@ -69,9 +71,14 @@ impl Plugin for GamePlugin {
 
         app.register_type::<Pickup>()
             .register_type::<PickupSensor>()
-            .register_type::<PlayerProgress>();
+            .register_type::<ConsumedPickupSensor>()
+            .register_type::<ThrownCube>()
+            .register_type::<PlayerProgress>()
+            .register_type::<Ability>()
+            .register_type::<CollectedPickups>();
 
-        app.insert_resource(PlayerProgress::default());
+        app.insert_resource(PlayerProgress::default())
+            .init_resource::<CollectedPickups>();
 
         #[cfg(feature = "devel")]
         {
@@ -79,10 +86,15 @@ impl Plugin for GamePlugin {
         }
 
         app.add_plugin(RapierPhysicsPlugin::<NoUserData>::default());
+        app.add_plugin(buttons::ButtonsPlugin);
+        app.add_plugin(checkpoints::CheckpointsPlugin);
         app.add_plugin(doors::DoorsPlugin);
+        app.add_plugin(lasers::LasersPlugin);
         app.add_plugin(physics::PhysicsPlugin);
         app.add_plugin(portal::PortalPlugin);
         app.add_plugin(render::RenderPlugin);
+        app.add_plugin(save::SavePlugin);
+        app.add_plugin(signals::SignalsPlugin);
         app.add_plugin(first_person_controller::FirstPersonControllerPlugin);
         app.add_plugin(input::InputPlugin);
         app.add_plugin(asset_processor::LevelsPlugin);
@@ -135,12 +147,37 @@ impl Default for PhysicsCubeBundle {
     }
 }
 
-#[derive(Debug, Clone, Resource, Default, Reflect, FromReflect, PartialEq, Eq)]
-pub enum PlayerProgress {
-    #[default]
-    GettingStarted,
-    HasPortalGun,
-    HasImprovedPortalGun,
+/// Ability of the player, granted by the pickups listing it in their `grants` extra.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Reflect, FromReflect, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Ability {
+    /// Shooting the orange portal.
+    PortalA,
+    /// Shooting the blue portal.
+    PortalB,
+    /// The orange portal attracts the bodies around it.
+    Attract,
+    /// The blue portal repels the bodies around it.
+    Repel,
+}
+
+/// Abilities the player unlocked.
+#[derive(
+    Debug, Clone, Resource, Default, Reflect, FromReflect, PartialEq, Eq, Serialize, Deserialize,
+)]
+pub struct PlayerProgress {
+    pub abilities: HashSet<Ability>,
+}
+
+impl PlayerProgress {
+    pub fn has(&self, ability: Ability) -> bool {
+        self.abilities.contains(&ability)
+    }
+
+    /// Whether the player has the portal gun, which shoots at least one of the portals.
+    pub fn has_portal_gun(&self) -> bool {
+        self.has(Ability::PortalA) || self.has(Ability::PortalB)
+    }
 }
 
 #[derive(Debug, Component, Default, Reflect, FromReflect)]
@@ -149,10 +186,63 @@ pub struct Pickup {
     pub id: u32,
 }
 
-#[derive(Debug, Component, Default, Reflect, FromReflect)]
+#[derive(Debug, Clone, Component, Default, Reflect, FromReflect)]
 #[reflect(Component)]
 pub struct PickupSensor {
     pub pickup_id: u32,
+    /// Abilities granted when the pickup is collected.
+    pub grants: Vec<Ability>,
+}
+
+/// Pickup sensor whose pickup was collected.
+#[derive(Debug, Component, Default, Reflect, FromReflect)]
+#[reflect(Component)]
+pub struct ConsumedPickupSensor {
+    pub sensor: PickupSensor,
+}
+
+/// Cube thrown by the player.
+#[derive(Debug, Component, Default, Reflect, FromReflect)]
+#[reflect(Component)]
+pub struct ThrownCube;
+
+/// IDs of the pickups the player collected.
+#[derive(Debug, Clone, Resource, Default, Reflect)]
+pub struct CollectedPickups {
+    pub ids: HashSet<u32>,
+}
+
+/// Turn off a pickup sensor once its pickup is collected.
+pub(crate) fn consume_pickup_sensor(
+    commands: &mut Commands,
+    sensor_entity: Entity,
+    sensor: PickupSensor,
+    source: Option<Mut<SignalSource>>,
+) {
+    // Keep the sensor node so its signal stays high.
+    if let Some(mut source) = source {
+        source.high = true;
+    }
+    commands
+        .entity(sensor_entity)
+        .remove::<PickupSensor>()
+        .insert(ConsumedPickupSensor { sensor });
+}
+
+/// Turn a consumed pickup sensor back on, once its pickup is given back.
+pub(crate) fn restore_pickup_sensor(
+    commands: &mut Commands,
+    sensor_entity: Entity,
+    sensor: PickupSensor,
+    source: Option<Mut<SignalSource>>,
+) {
+    if let Some(mut source) = source {
+        source.high = false;
+    }
+    commands
+        .entity(sensor_entity)
+        .remove::<ConsumedPickupSensor>()
+        .insert(sensor);
 }
 
 const CUBE_SIZE: f32 = 0.2;
@@ -187,29 +277,29 @@ fn throw_cube(
         if input.just_pressed(Actions::ShootCube) {
             let mut cube_trf = cam_trf.compute_transform();
             cube_trf.translation += cam_trf.forward();
-            commands.spawn(PhysicsCubeBundle {
-                pbr_bundle: PbrBundle {
-                    mesh: res.cube_mesh.clone(),
-                    material: res.cube_material.clone(),
-                    transform: cube_trf,
-                    ..default()
-                },
-                initial_velocity: Velocity {
-                    linvel: cube_trf.forward() * 5.,
+            commands.spawn((
+                PhysicsCubeBundle {
+                    pbr_bundle: PbrBundle {
+                        mesh: res.cube_mesh.clone(),
+                        material: res.cube_material.clone(),
+                        transform: cube_trf,
+                        ..default()
+                    },
+                    initial_velocity: Velocity {
+                        linvel: cube_trf.forward() * 5.,
+                        ..default()
+                    },
                     ..default()
                 },
-                ..default()
-            });
+                ThrownCube,
+            ));
         }
     }
 }
 
-pub const LOBBY_LEVEL_NAME: &str = "lobby";
-pub const LOBBY_LEVEL_FILE: &str = "levels/level1.glb";
-
 /// Perform game initialization
 fn game_startup(assets: Res<AssetServer>, mut level_manager: ResMut<LevelProcessor>) {
-    level_manager.load_level(LOBBY_LEVEL_FILE, LOBBY_LEVEL_NAME.to_owned(), &assets);
+    level_manager.load_manifest(LEVEL_MANIFEST_FILE, &assets);
 }
 
 fn load_level_when_ready(
@@ -223,9 +313,9 @@ fn load_level_when_ready(
         match event {
             AssetEvent::Created { handle } => {
                 let level = levels.get(handle).unwrap();
-                if level.name == LOBBY_LEVEL_NAME {
+                if Some(level.name.as_str()) == level_manager.first_level() {
                     level_manager
-                        .instantiate_level(&mut commands, LOBBY_LEVEL_NAME)
+                        .instantiate_level(&mut commands, &level.name)
                         .expect("Can not instantiate level");
                     *loaded = true;
                 }
@@ -239,8 +329,10 @@ fn load_level_when_ready(
 fn process_pickups(
     mut commands: Commands,
     mut collisions: EventReader<CollisionEvent>,
-    mut sensors_query: Query<(&PickupSensor, Entity)>,
-    pickups_query: Query<(&Pickup, Entity)>,
+    mut sensors_query: Query<(&PickupSensor, Option<&mut SignalSource>, Entity)>,
+    mut pickups_query: Query<(&Pickup, &mut Visibility)>,
+    mut collected: ResMut<CollectedPickups>,
+    mut progress: ResMut<PlayerProgress>,
 ) {
     for collision in collisions.iter() {
         match collision {
@@ -248,21 +340,23 @@ fn process_pickups(
                 let maybe_sensor_entity = sensors_query
                     .get(*collider_a)
                     .or_else(|_| sensors_query.get(*collider_b))
-                    .map(|r| r.1);
+                    .map(|r| r.2);
                 if let Ok(sensor_entity) = maybe_sensor_entity {
-                    let (sensor, sensor_entity) = sensors_query.get_mut(sensor_entity).unwrap();
-                    info!("Pickup {} activated", sensor.pickup_id);
-                    if sensor.pickup_id == 1 {
-                        commands.insert_resource(PlayerProgress::HasPortalGun);
-                    } else if sensor.pickup_id == 2 {
-                        commands.insert_resource(PlayerProgress::HasImprovedPortalGun);
-                    }
-                    for (pickup, pickup_entity) in &pickups_query {
+                    let (sensor, source, sensor_entity) =
+                        sensors_query.get_mut(sensor_entity).unwrap();
+                    info!(
+                        "Pickup {} activated, granting {:?}",
+                        sensor.pickup_id, sensor.grants
+                    );
+                    progress.abilities.extend(sensor.grants.iter().copied());
+                    // Pickups are hidden rather than despawned, so a section reset gives them back.
+                    for (pickup, mut visibility) in &mut pickups_query {
                         if pickup.id == sensor.pickup_id {
-                            commands.entity(pickup_entity).despawn_recursive();
+                            visibility.is_visible = false;
                         }
                     }
-                    commands.entity(sensor_entity).despawn_recursive();
+                    collected.ids.insert(sensor.pickup_id);
+                    consume_pickup_sensor(&mut commands, sensor_entity, sensor.clone(), source);
                 }
             }
             CollisionEvent::Stopped(_collider_a, _collider_b, _flags) => {}
@@ -25,6 +25,9 @@ pub enum Actions {
     ShootCube,
     Jump,
     Grab,
+    QuickSave,
+    QuickLoad,
+    ResetSection,
 }
 
 pub fn default_input_map() -> InputMap<Actions> {
@@ -37,6 +40,9 @@ pub fn default_input_map() -> InputMap<Actions> {
         (KeyCode::Q, Actions::ShootCube),
         (KeyCode::LShift, Actions::Sprint),
         (KeyCode::Space, Actions::Jump),
+        (KeyCode::F5, Actions::QuickSave),
+        (KeyCode::F9, Actions::QuickLoad),
+        (KeyCode::R, Actions::ResetSection),
     ]);
     input_map.insert(DualAxis::mouse_motion(), Actions::Aim);
     input_map.insert(MouseButton::Left, Actions::ShootA);
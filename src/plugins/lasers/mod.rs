@@ -0,0 +1,218 @@
+//! Laser puzzle elements: emitters fire a beam which goes through the portals and bounces off
+//! redirector props, and the receivers it hits open their doors.
+
+use bevy::{prelude::*, reflect::FromReflect};
+use bevy_rapier3d::prelude::*;
+
+use super::{
+    doors::Door,
+    physics::*,
+    portal::{PortalRaycast, RaySegment},
+};
+
+pub struct LasersPlugin;
+
+impl Plugin for LasersPlugin {
+    fn build(&self, app: &mut App) {
+        app.register_type::<LaserEmitter>()
+            .register_type::<LaserReceiver>()
+            .register_type::<LaserRedirector>()
+            .register_type::<LaserResources>()
+            .add_startup_system(load_laser_assets)
+            .add_system(trace_laser_beams)
+            .add_system(despawn_orphan_laser_beams);
+    }
+}
+
+/// Maximum length of a laser beam, summed over all its segments.
+const LASER_RANGE: f32 = 100.;
+
+/// Maximum number of redirectors a laser beam bounces off.
+const MAX_LASER_BOUNCES: usize = 8;
+
+/// Width of the rendered laser beams.
+const LASER_BEAM_WIDTH: f32 = 0.03;
+
+/// Level node firing a laser beam along its local Z axis.
+#[derive(Debug, Default, Component, Reflect, FromReflect)]
+#[reflect(Component)]
+pub struct LaserEmitter {
+    /// Entities rendering the segments of the beam.
+    pub beam_segments: Vec<Entity>,
+}
+
+/// Level node opening the doors with the given ID while a laser beam hits it.
+#[derive(Debug, Default, Component, Reflect, FromReflect)]
+#[reflect(Component)]
+pub struct LaserReceiver {
+    pub doors_id: u32,
+    pub door_entities: Vec<Entity>,
+    pub lit: bool,
+}
+
+/// Segment of the beam of a laser emitter.
+#[derive(Debug, Component)]
+pub struct LaserBeam {
+    pub emitter: Entity,
+}
+
+/// Prop reflecting the laser beams hitting it.
+#[derive(Debug, Default, Component, Reflect, FromReflect)]
+#[reflect(Component)]
+pub struct LaserRedirector;
+
+#[derive(Debug, Default, Reflect, Resource)]
+pub struct LaserResources {
+    beam_mesh: Handle<Mesh>,
+    beam_material: Handle<StandardMaterial>,
+}
+
+fn load_laser_assets(
+    mut commands: Commands,
+    mut meshes: ResMut<Assets<Mesh>>,
+    mut materials: ResMut<Assets<StandardMaterial>>,
+) {
+    let beam_mesh = meshes.add(
+        shape::Box {
+            min_x: -LASER_BEAM_WIDTH / 2.,
+            max_x: LASER_BEAM_WIDTH / 2.,
+            min_y: -LASER_BEAM_WIDTH / 2.,
+            max_y: LASER_BEAM_WIDTH / 2.,
+            min_z: -0.5,
+            max_z: 0.5,
+        }
+        .into(),
+    );
+    let beam_material = materials.add(StandardMaterial {
+        base_color: Color::RED,
+        emissive: Color::RED,
+        unlit: true,
+        ..default()
+    });
+    commands.insert_resource(LaserResources {
+        beam_mesh,
+        beam_material,
+    });
+}
+
+/// Return the segments of the beam fired from the given transform, and the entity it ends on.
+fn trace_beam(
+    emitter: &GlobalTransform,
+    raycast: &PortalRaycast,
+    redirectors_query: &Query<(), With<LaserRedirector>>,
+) -> (Vec<RaySegment>, Option<Entity>) {
+    let filter = QueryFilter::new().groups(InteractionGroups::new(
+        RAYCAST_GROUP.bits().into(),
+        (WALLS_GROUP | GROUND_GROUP | PROPS_GROUP | PLAYER_GROUP | LASER_RECEIVERS_GROUP)
+            .bits()
+            .into(),
+    ));
+    let mut segments = Vec::new();
+    let mut origin = emitter.translation();
+    let mut direction = emitter.back();
+    let mut remaining = LASER_RANGE;
+    for _ in 0..=MAX_LASER_BOUNCES {
+        let ray = raycast.cast_ray(origin, direction, remaining, false, filter);
+        remaining -= ray
+            .segments
+            .iter()
+            .map(|segment| segment.length)
+            .sum::<f32>();
+        segments.extend(ray.segments);
+        match ray.hit {
+            Some((entity, intersection)) if redirectors_query.contains(entity) => {
+                let incoming = segments.last().unwrap().direction;
+                direction = incoming - 2. * incoming.dot(intersection.normal) * intersection.normal;
+                // Start off the surface so the reflected beam doesn't hit it again.
+                origin = intersection.point + intersection.normal * 0.01;
+            }
+            Some((entity, _)) => return (segments, Some(entity)),
+            None => return (segments, None),
+        }
+    }
+    (segments, None)
+}
+
+/// Trace the laser beams, update their meshes, and open or close the doors of the receivers.
+#[allow(clippy::too_many_arguments)]
+fn trace_laser_beams(
+    mut commands: Commands,
+    mut emitters_query: Query<(&mut LaserEmitter, &GlobalTransform, Entity)>,
+    mut receivers_query: Query<(&mut LaserReceiver, Entity)>,
+    mut beams_query: Query<(&mut Transform, &mut Visibility), Without<LaserEmitter>>,
+    mut doors_query: Query<&mut Door>,
+    redirectors_query: Query<(), With<LaserRedirector>>,
+    raycast: PortalRaycast,
+    laser_res: Res<LaserResources>,
+) {
+    let mut lit_receivers = Vec::new();
+    for (mut emitter, emitter_trf, emitter_entity) in &mut emitters_query {
+        let (segments, target) = trace_beam(emitter_trf, &raycast, &redirectors_query);
+        if let Some(target) = target {
+            lit_receivers.push(target);
+        }
+
+        while emitter.beam_segments.len() < segments.len() {
+            let beam = commands
+                .spawn((
+                    PbrBundle {
+                        mesh: laser_res.beam_mesh.clone(),
+                        material: laser_res.beam_material.clone(),
+                        ..default()
+                    },
+                    Name::from("Laser beam"),
+                    LaserBeam {
+                        emitter: emitter_entity,
+                    },
+                ))
+                .id();
+            emitter.beam_segments.push(beam);
+        }
+        for (i, beam) in emitter.beam_segments.iter().enumerate() {
+            if let Ok((mut transform, mut visibility)) = beams_query.get_mut(*beam) {
+                match segments.get(i) {
+                    Some(segment) => {
+                        *transform = Transform {
+                            translation: segment.origin + segment.direction * segment.length / 2.,
+                            rotation: Quat::from_rotation_arc(Vec3::Z, segment.direction),
+                            scale: Vec3::new(1., 1., segment.length),
+                        };
+                        visibility.is_visible = true;
+                    }
+                    None => visibility.is_visible = false,
+                }
+            }
+        }
+    }
+
+    for (mut receiver, entity) in &mut receivers_query {
+        let lit = lit_receivers.contains(&entity);
+        if receiver.lit == lit {
+            continue;
+        }
+        receiver.lit = lit;
+        info!(
+            "Laser receiver for door {} {}",
+            receiver.doors_id,
+            if lit { "lit" } else { "unlit" }
+        );
+        for door_entity in &receiver.door_entities {
+            if let Ok(mut door) = doors_query.get_mut(*door_entity) {
+                door.trigger(lit);
+            }
+        }
+    }
+}
+
+/// Despawn the beams of the emitters which were despawned along with their level.
+fn despawn_orphan_laser_beams(
+    mut commands: Commands,
+    beams_query: Query<(&LaserBeam, Entity)>,
+    emitters_query: Query<(), With<LaserEmitter>>,
+) {
+    for (beam, entity) in &beams_query {
+        if !emitters_query.contains(beam.emitter) {
+            commands.entity(entity).despawn_recursive();
+        }
+    }
+}
@@ -2,10 +2,15 @@
 pub mod debug;
 
 pub mod asset_processor;
+pub mod buttons;
+pub mod checkpoints;
 pub mod doors;
 pub mod first_person_controller;
 pub mod game;
 pub mod input;
+pub mod lasers;
 pub mod physics;
 pub mod portal;
 pub mod render;
+pub mod save;
+pub mod signals;
@@ -9,6 +9,9 @@ pub const RAYCAST_GROUP: Group = Group::GROUP_5;
 pub const GROUND_GROUP: Group = Group::GROUP_6;
 pub const DOOR_SENSORS_GROUP: Group = Group::GROUP_7;
 pub const LEVEL_TRANSITION_SENSORS_GROUP: Group = Group::GROUP_8;
+/// Level geometry on which portals can not be placed, along with the walls or ground group.
+pub const NON_PORTALABLE_GROUP: Group = Group::GROUP_9;
+pub const LASER_RECEIVERS_GROUP: Group = Group::GROUP_10;
 pub const ALL_GROUPS: Group = Group::ALL;
 
 pub struct PhysicsPlugin;
@@ -0,0 +1,218 @@
+//! Seamless portal crossing: while a prop straddles a portal, a visual-only clone is shown on the
+//! other side of the pair, and both are clipped by their portal surface.
+//!
+//! The prop is teleported when its center crosses the portal, at which point it straddles the
+//! linked portal and the prop and its clone swap roles.
+
+use bevy::prelude::*;
+use bevy_rapier3d::prelude::*;
+
+use crate::plugins::first_person_controller::FirstPersonController;
+
+use super::{
+    geometry, material::ClippedPropMaterial, Portal, PortalPlugin, PortalTeleport,
+    PORTAL_MESH_DEPTH,
+};
+
+/// Visual-only copy of a prop straddling a portal, shown at the linked portal.
+#[derive(Debug, Component)]
+pub struct PortalClone {
+    pub original: Entity,
+}
+
+/// Prop straddling a portal, rendered clipped along with its clone.
+#[derive(Debug, Component)]
+pub struct Straddling {
+    /// The portal the prop goes through.
+    portal: Entity,
+    clone: Entity,
+    /// Meshes of the prop, with their own material and the clipped one replacing it.
+    meshes: Vec<(
+        Entity,
+        Handle<StandardMaterial>,
+        Handle<ClippedPropMaterial>,
+    )>,
+    /// Clipped materials of the clone meshes.
+    clone_materials: Vec<Handle<ClippedPropMaterial>>,
+}
+
+/// Return the portal straddled by a prop, along with the transform from that portal to the linked
+/// one, and the clipping planes of both portals.
+fn find_straddled_portal(
+    prop_transform: &Transform,
+    radius: f32,
+    portal_query: &Query<(&Portal, &GlobalTransform, Entity), Without<PortalTeleport>>,
+) -> Option<(Entity, Transform, Vec4, Vec4)> {
+    portal_query.iter().find_map(|(portal, trf, entity)| {
+        let (_, linked_trf, _) = portal_query.get(portal.linked_portal?).ok()?;
+        let (scale, _, _) = trf.to_scale_rotation_translation();
+        let normal = trf.back();
+        let offset = prop_transform.translation - (trf.translation() + normal * PORTAL_MESH_DEPTH);
+        let distance = offset.dot(normal);
+        if distance.abs() < radius
+            && offset.dot(trf.right()).abs() < scale.x / 2.
+            && offset.dot(trf.up()).abs() < scale.y / 2.
+        {
+            Some((
+                entity,
+                geometry::portal_to_portal(
+                    &trf.compute_transform(),
+                    &linked_trf.compute_transform(),
+                ),
+                PortalPlugin::get_portal_plane(trf),
+                PortalPlugin::get_portal_plane(linked_trf),
+            ))
+        } else {
+            None
+        }
+    })
+}
+
+/// Collect the meshes of a prop, which are either on the prop itself or its children.
+fn prop_meshes(
+    entity: Entity,
+    children_query: &Query<&Children>,
+    meshes_query: &Query<
+        (&Handle<Mesh>, &Handle<StandardMaterial>, &Transform),
+        Without<PortalClone>,
+    >,
+) -> Vec<Entity> {
+    let children = children_query
+        .get(entity)
+        .map(|children| children.to_vec())
+        .unwrap_or_default();
+    std::iter::once(entity)
+        .chain(children)
+        .filter(|entity| meshes_query.contains(*entity))
+        .collect()
+}
+
+/// Spawn the clones of the props entering a portal, move them along with their prop, and despawn
+/// them once the prop left the portal.
+#[allow(clippy::too_many_arguments)]
+pub fn update_portal_clones(
+    mut commands: Commands,
+    portal_query: Query<(&Portal, &GlobalTransform, Entity), Without<PortalTeleport>>,
+    mut props_query: Query<
+        (&Transform, &Collider, Option<&mut Straddling>, Entity),
+        (With<PortalTeleport>, Without<FirstPersonController>),
+    >,
+    mut clones_query: Query<&mut Transform, (With<PortalClone>, Without<PortalTeleport>)>,
+    children_query: Query<&Children>,
+    meshes_query: Query<
+        (&Handle<Mesh>, &Handle<StandardMaterial>, &Transform),
+        Without<PortalClone>,
+    >,
+    std_materials: Res<Assets<StandardMaterial>>,
+    mut clipped_materials: ResMut<Assets<ClippedPropMaterial>>,
+) {
+    for (prop_transform, collider, straddling, entity) in &mut props_query {
+        let radius = collider.raw.compute_local_aabb().half_extents().norm()
+            * prop_transform.scale.max_element();
+        let straddled = find_straddled_portal(prop_transform, radius, &portal_query);
+        match (straddled, straddling) {
+            (Some((portal, portal_to_linked, plane, linked_plane)), None) => {
+                let mut meshes = Vec::new();
+                let mut clone_materials = Vec::new();
+                let mut clone_meshes = Vec::new();
+                for mesh_entity in prop_meshes(entity, &children_query, &meshes_query) {
+                    let (mesh, material, transform) = meshes_query.get(mesh_entity).unwrap();
+                    let std_material = match std_materials.get(material) {
+                        Some(std_material) => std_material,
+                        None => continue,
+                    };
+                    let clipped = clipped_materials
+                        .add(ClippedPropMaterial::from_standard(std_material, plane));
+                    let clone_material = clipped_materials.add(ClippedPropMaterial::from_standard(
+                        std_material,
+                        linked_plane,
+                    ));
+                    commands
+                        .entity(mesh_entity)
+                        .remove::<Handle<StandardMaterial>>()
+                        .insert(clipped.clone());
+                    // The meshes on the prop itself are at the origin of the clone.
+                    let transform = if mesh_entity == entity {
+                        Transform::IDENTITY
+                    } else {
+                        *transform
+                    };
+                    clone_meshes.push((mesh.clone(), clone_material.clone(), transform));
+                    meshes.push((mesh_entity, material.clone(), clipped));
+                    clone_materials.push(clone_material);
+                }
+
+                let clone = commands
+                    .spawn((
+                        SpatialBundle::from_transform(portal_to_linked * *prop_transform),
+                        PortalClone { original: entity },
+                        Name::from("Portal clone"),
+                    ))
+                    .with_children(|parent| {
+                        for (mesh, material, transform) in clone_meshes {
+                            parent.spawn(MaterialMeshBundle {
+                                mesh,
+                                material,
+                                transform,
+                                ..default()
+                            });
+                        }
+                    })
+                    .id();
+                commands.entity(entity).insert(Straddling {
+                    portal,
+                    clone,
+                    meshes,
+                    clone_materials,
+                });
+            }
+            (Some((portal, portal_to_linked, plane, linked_plane)), Some(mut straddling)) => {
+                // The prop was teleported, it now straddles the linked portal.
+                if straddling.portal != portal {
+                    straddling.portal = portal;
+                    for (_, _, material) in &straddling.meshes {
+                        if let Some(material) = clipped_materials.get_mut(material) {
+                            material.uniform.clip_plane = plane;
+                        }
+                    }
+                    for material in &straddling.clone_materials {
+                        if let Some(material) = clipped_materials.get_mut(material) {
+                            material.uniform.clip_plane = linked_plane;
+                        }
+                    }
+                }
+                if let Ok(mut clone_transform) = clones_query.get_mut(straddling.clone) {
+                    *clone_transform = portal_to_linked * *prop_transform;
+                }
+            }
+            (None, Some(straddling)) => {
+                for (mesh_entity, material, clipped) in &straddling.meshes {
+                    commands
+                        .entity(*mesh_entity)
+                        .remove::<Handle<ClippedPropMaterial>>()
+                        .insert(material.clone());
+                    clipped_materials.remove(clipped);
+                }
+                for material in &straddling.clone_materials {
+                    clipped_materials.remove(material);
+                }
+                commands.entity(straddling.clone).despawn_recursive();
+                commands.entity(entity).remove::<Straddling>();
+            }
+            (None, None) => {}
+        }
+    }
+}
+
+/// Despawn the clones of the props which were despawned while straddling a portal.
+pub fn despawn_orphan_portal_clones(
+    mut commands: Commands,
+    clones_query: Query<(&PortalClone, Entity)>,
+    props_query: Query<(), (With<Straddling>, With<PortalTeleport>)>,
+) {
+    for (clone, entity) in &clones_query {
+        if !props_query.contains(clone.original) {
+            commands.entity(entity).despawn_recursive();
+        }
+    }
+}
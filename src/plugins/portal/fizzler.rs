@@ -0,0 +1,230 @@
+//! Fizzlers are level volumes which close the player portals when the player goes through them,
+//! and dissolve the props entering them.
+//!
+//! The dissolved level props are only hidden, so resetting their section brings them back.
+
+use bevy::{prelude::*, reflect::FromReflect};
+use bevy_rapier3d::prelude::*;
+
+use crate::plugins::{
+    asset_processor::LevelProp, first_person_controller::FirstPersonController, physics::*,
+};
+
+use super::{close_player_portals, Portal, PortalTeleport};
+
+/// Duration of the dissolve effect of the props destroyed by a fizzler.
+const DISSOLVE_DURATION: f32 = 0.5;
+
+/// Level node tagged with the `fizzler` extra, turned into a sensor once the level is spawned.
+#[derive(Debug, Default, Component, Reflect, FromReflect)]
+#[reflect(Component)]
+pub struct Fizzler;
+
+/// Prop destroyed by a fizzler, shrinking until it is despawned.
+#[derive(Debug, Component)]
+pub struct Dissolving {
+    timer: Timer,
+    initial_scale: Vec3,
+}
+
+/// Level prop dissolved by a fizzler, hidden until its section is reset.
+#[derive(Debug, Default, Component)]
+pub struct Dissolved;
+
+/// Close the player portals when the player goes through a fizzler, and dissolve the props going
+/// through it along with the one the player carries.
+pub fn fizzle_on_sensor_contact(
+    mut commands: Commands,
+    mut collisions: EventReader<CollisionEvent>,
+    fizzlers_query: Query<(), With<Fizzler>>,
+    mut player_query: Query<&mut FirstPersonController>,
+    props_query: Query<(&Transform, &GlobalTransform), With<PortalTeleport>>,
+    portal_query: Query<(&Portal, Entity)>,
+) {
+    for collision in collisions.iter() {
+        let (collider_a, collider_b) = match collision {
+            CollisionEvent::Started(collider_a, collider_b, _flags) => (collider_a, collider_b),
+            CollisionEvent::Stopped(_collider_a, _collider_b, _flags) => continue,
+        };
+        let other = if fizzlers_query.contains(*collider_a) {
+            *collider_b
+        } else if fizzlers_query.contains(*collider_b) {
+            *collider_a
+        } else {
+            continue;
+        };
+
+        if let Ok(mut controller) = player_query.get_mut(other) {
+            info!("Player went through a fizzler");
+            close_player_portals(&mut commands, &portal_query);
+            if let Some(grabbed_object) = controller.grabbed_object.take() {
+                if let Ok((transform, global_transform)) = props_query.get(grabbed_object) {
+                    dissolve(
+                        &mut commands,
+                        grabbed_object,
+                        transform,
+                        global_transform,
+                        true,
+                    );
+                }
+            }
+        } else if let Ok((transform, global_transform)) = props_query.get(other) {
+            info!("Prop {:?} went through a fizzler", other);
+            let mut carried = false;
+            if let Ok(mut controller) = player_query.get_single_mut() {
+                if controller.grabbed_object == Some(other) {
+                    controller.grabbed_object = None;
+                    carried = true;
+                }
+            }
+            dissolve(&mut commands, other, transform, global_transform, carried);
+        }
+    }
+}
+
+/// Detach the prop from the physics simulation and start its dissolve effect.
+fn dissolve(
+    commands: &mut Commands,
+    entity: Entity,
+    transform: &Transform,
+    global_transform: &GlobalTransform,
+    carried: bool,
+) {
+    let mut entity_commands = commands.entity(entity);
+    // Carried props are children of the player camera, leave them where they are.
+    if carried {
+        entity_commands
+            .remove_parent()
+            .insert(global_transform.compute_transform());
+    }
+    entity_commands.remove::<PortalTeleport>().insert((
+        RigidBody::Fixed,
+        CollisionGroups::new(Group::NONE, Group::NONE),
+        Dissolving {
+            timer: Timer::from_seconds(DISSOLVE_DURATION, TimerMode::Once),
+            initial_scale: transform.scale,
+        },
+    ));
+}
+
+/// Shrink the dissolving props, and despawn them when the effect is over. The level props are
+/// hidden instead, until their section is reset.
+pub fn animate_dissolve(
+    mut commands: Commands,
+    mut dissolving_query: Query<(&mut Dissolving, &mut Transform, Option<&LevelProp>, Entity)>,
+    time: Res<Time>,
+) {
+    for (mut dissolving, mut transform, level_prop, entity) in &mut dissolving_query {
+        dissolving.timer.tick(time.delta());
+        if dissolving.timer.finished() {
+            match level_prop {
+                Some(_) => {
+                    commands
+                        .entity(entity)
+                        .remove::<Dissolving>()
+                        .insert((Dissolved, Visibility::INVISIBLE));
+                }
+                None => commands.entity(entity).despawn_recursive(),
+            }
+        } else {
+            transform.scale = dissolving.initial_scale * dissolving.timer.percent_left();
+        }
+    }
+}
+
+/// Bring back a dissolved level prop at the given transform.
+pub fn restore_dissolved_prop(commands: &mut Commands, entity: Entity, transform: Transform) {
+    commands
+        .entity(entity)
+        .remove::<Dissolving>()
+        .remove::<Dissolved>()
+        .insert((
+            transform,
+            Velocity::default(),
+            RigidBody::Dynamic,
+            CollisionGroups::new(PROPS_GROUP, ALL_GROUPS),
+            PortalTeleport,
+            Visibility::VISIBLE,
+        ));
+}
+
+#[cfg(test)]
+mod tests {
+    use bevy::{
+        ecs::system::SystemState,
+        utils::{Duration, Instant},
+    };
+    use euclid::Angle;
+
+    use crate::plugins::{asset_processor::NodeSection, checkpoints::SectionProps};
+
+    use super::*;
+
+    #[test]
+    fn section_reset_brings_back_dissolved_prop() {
+        let mut app = App::new();
+        app.init_resource::<Time>().add_system(animate_dissolve);
+        let authored_transform = Transform::from_xyz(1., 2., 3.);
+        let transform = Transform::from_xyz(4., 5., 6.);
+        let prop = app
+            .world
+            .spawn((
+                LevelProp { authored_transform },
+                NodeSection {
+                    section_name: "Section".to_owned(),
+                },
+                transform,
+                GlobalTransform::from(transform),
+                Velocity::default(),
+                Visibility::VISIBLE,
+                PortalTeleport,
+            ))
+            .id();
+
+        let mut state = SystemState::<Commands>::new(&mut app.world);
+        let mut commands = state.get_mut(&mut app.world);
+        dissolve(
+            &mut commands,
+            prop,
+            &transform,
+            &GlobalTransform::from(transform),
+            false,
+        );
+        state.apply(&mut app.world);
+
+        let start = Instant::now();
+        for seconds in [0., DISSOLVE_DURATION * 2.] {
+            app.world
+                .resource_mut::<Time>()
+                .update_with_instant(start + Duration::from_secs_f32(seconds));
+            app.update();
+        }
+        assert!(app.world.get::<Dissolved>(prop).is_some());
+        assert!(!app.world.get::<Visibility>(prop).unwrap().is_visible);
+        assert!(app.world.get::<PortalTeleport>(prop).is_none());
+
+        let mut state = SystemState::<(Commands, SectionProps)>::new(&mut app.world);
+        let (mut commands, mut props) = state.get_mut(&mut app.world);
+        let mut controller = FirstPersonController {
+            yaw: Angle::zero(),
+            pitch: Angle::zero(),
+            camera_anchor: prop,
+            weapon_node: prop,
+            grabbed_object: None,
+        };
+        props.reset(&mut commands, "Section", &mut controller);
+        state.apply(&mut app.world);
+
+        assert!(app.world.get::<Dissolved>(prop).is_none());
+        assert!(app.world.get::<Visibility>(prop).unwrap().is_visible);
+        assert!(app.world.get::<PortalTeleport>(prop).is_some());
+        assert_eq!(
+            *app.world.get::<RigidBody>(prop).unwrap(),
+            RigidBody::Dynamic
+        );
+        assert_eq!(
+            *app.world.get::<Transform>(prop).unwrap(),
+            authored_transform
+        );
+    }
+}
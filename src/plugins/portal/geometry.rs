@@ -4,9 +4,18 @@ use bevy::prelude::*;
 use bevy_rapier3d::prelude::*;
 use euclid::Angle;
 
-use crate::plugins::{first_person_controller::FirstPersonController, physics::*};
+use crate::plugins::{doors::Door, first_person_controller::FirstPersonController, physics::*};
 
-use super::PORTAL_MESH_DEPTH;
+use super::{PortalPlacementFailure, PORTAL_MESH_DEPTH};
+
+/// Half the side of the portal quad shot by the player.
+pub const PORTAL_HALF_EXTENT: f32 = 1.;
+
+/// Distance in front of a surface from which the portal placement is probed.
+const PORTAL_PROBE_DISTANCE: f32 = 0.05;
+
+/// Fraction of the portal half extent probed on the surface, so edges flush with a wall still fit.
+const PORTAL_PROBE_INSET: f32 = 0.95;
 
 pub fn adjust_portal_origin_to_obstacles(
     base_location: Vec3,
@@ -80,6 +89,82 @@ pub fn adjust_portal_origin_to_obstacles(
     corrected_position
 }
 
+/// Check that a portal centered on `surface` lies flat on that single surface, without obstacles
+/// in front of it.
+pub fn check_portal_fits(
+    center: Vec3,
+    normal: Vec3,
+    up: Vec3,
+    surface: Entity,
+    rapier: &RapierContext,
+) -> Result<(), PortalPlacementFailure> {
+    let right = up.cross(normal);
+    let filter = QueryFilter::new().groups(InteractionGroups::new(
+        RAYCAST_GROUP.bits().into(),
+        (WALLS_GROUP | GROUND_GROUP).bits().into(),
+    ));
+    let probe_origin = center + normal * PORTAL_PROBE_DISTANCE;
+    let offset = PORTAL_HALF_EXTENT * PORTAL_PROBE_INSET;
+
+    // Nothing must stick out of the surface across the portal.
+    for direction in [up, -up, right, -right] {
+        if rapier
+            .cast_ray(probe_origin, direction, offset, true, filter)
+            .is_some()
+        {
+            return Err(PortalPlacementFailure::SurfaceTooSmall);
+        }
+    }
+
+    // The surface must be under the corners and the middle of the edges of the portal.
+    for x in [-offset, 0., offset] {
+        for y in [-offset, 0., offset] {
+            match rapier.cast_ray_and_get_normal(
+                probe_origin + right * x + up * y,
+                -normal,
+                2. * PORTAL_PROBE_DISTANCE,
+                false,
+                filter,
+            ) {
+                Some((entity, impact))
+                    if entity == surface && impact.normal.dot(normal) > 0.999 => {}
+                Some(_) => return Err(PortalPlacementFailure::UnevenSurface),
+                None => return Err(PortalPlacementFailure::SurfaceTooSmall),
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Return whether a portal centered at `center` would overlap the given portal.
+pub fn overlaps_portal(center: Vec3, normal: Vec3, up: Vec3, portal: &GlobalTransform) -> bool {
+    let other_normal = portal.back();
+    let other_center = portal.translation() + other_normal * PORTAL_MESH_DEPTH;
+    let offset = other_center - center;
+    // Portals on different surfaces never overlap.
+    if other_normal.dot(normal) < 0.999 || offset.dot(normal).abs() > PORTAL_PROBE_DISTANCE {
+        return false;
+    }
+    let right = up.cross(normal);
+    offset.dot(right).abs() < 2. * PORTAL_HALF_EXTENT
+        && offset.dot(up).abs() < 2. * PORTAL_HALF_EXTENT
+}
+
+/// Return whether the collider, or one of its ancestors, is moved by physics or animations.
+pub fn is_moving_body(
+    entity: Entity,
+    bodies_query: &Query<(Option<&RigidBody>, Option<&Door>, Option<&Parent>)>,
+) -> bool {
+    let mut current = Some(entity);
+    while let Some((rigid_body, door, parent)) = current.and_then(|e| bodies_query.get(e).ok()) {
+        if door.is_some() || matches!(rigid_body, Some(body) if *body != RigidBody::Fixed) {
+            return true;
+        }
+        current = parent.map(|parent| parent.get());
+    }
+    false
+}
+
 pub fn portal_to_portal(
     render_portal_transform: &Transform,
     linked_portal_transform: &Transform,
@@ -133,3 +218,53 @@ pub fn adjust_player_camera_on_teleport(
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use std::f32::consts::FRAC_PI_2;
+
+    use super::*;
+
+    /// Check that entering a portal maps to exiting the linked portal.
+    fn assert_exits_linked(portal: Transform, linked: Transform) {
+        let transform = portal_to_portal(&portal, &linked);
+        let exit_direction = transform.rotation * portal.forward();
+        assert!(exit_direction.abs_diff_eq(linked.back(), 1e-5));
+    }
+
+    #[test]
+    fn same_facing_portals_turn_around() {
+        let portal = Transform::IDENTITY;
+        let linked = Transform::from_xyz(10., 0., 0.);
+        let transform = portal_to_portal(&portal, &linked);
+        assert!((transform.rotation * Vec3::NEG_Z).abs_diff_eq(Vec3::Z, 1e-5));
+        assert_exits_linked(portal, linked);
+    }
+
+    #[test]
+    fn opposite_portals_keep_direction() {
+        let portal = Transform::IDENTITY;
+        let linked = Transform::from_xyz(0., 0., -10.).with_rotation(Quat::from_rotation_y(PI));
+        let transform = portal_to_portal(&portal, &linked);
+        assert!((transform.rotation * Vec3::NEG_Z).abs_diff_eq(Vec3::NEG_Z, 1e-5));
+        assert_exits_linked(portal, linked);
+    }
+
+    #[test]
+    fn yawed_portals_rotate_direction() {
+        let portal = Transform::from_xyz(2., 1., 0.).with_rotation(Quat::from_rotation_y(PI));
+        let linked =
+            Transform::from_xyz(0., 3., 5.).with_rotation(Quat::from_rotation_y(FRAC_PI_2));
+        assert_exits_linked(portal, linked);
+    }
+
+    #[test]
+    fn portal_offsets_are_kept() {
+        let portal = Transform::IDENTITY;
+        let linked = Transform::from_xyz(10., 0., 0.).with_rotation(Quat::from_rotation_y(PI));
+        let transform = portal_to_portal(&portal, &linked);
+        // Facing portals act like a doorway, the offset from the portal center is kept.
+        let left = transform * Vec3::new(-1., 0., 0.) - transform * Vec3::ZERO;
+        assert!(left.abs_diff_eq(Vec3::new(-1., 0., 0.), 1e-5));
+    }
+}
@@ -48,3 +48,57 @@ impl Material for ClosedPortalMaterial {
 pub struct ClosedPortalUniform {
     pub color: Color,
 }
+
+/// Material of the props straddling a portal, hiding the part behind the portal surface.
+#[derive(AsBindGroup, Debug, Clone, TypeUuid, Reflect)]
+#[uuid = "8f3c2d5e-6a1b-4c7d-9e0f-2b4a6c8d1e3f"]
+pub struct ClippedPropMaterial {
+    #[uniform(0)]
+    pub uniform: ClippedPropUniform,
+    #[texture(1)]
+    #[sampler(2)]
+    pub base_color_texture: Option<Handle<Image>>,
+}
+
+impl ClippedPropMaterial {
+    /// Create a clipped copy of a standard material.
+    pub fn from_standard(material: &StandardMaterial, clip_plane: Vec4) -> ClippedPropMaterial {
+        ClippedPropMaterial {
+            uniform: ClippedPropUniform {
+                base_color: material.base_color,
+                clip_plane,
+                perceptual_roughness: material.perceptual_roughness,
+                metallic: material.metallic,
+                reflectance: material.reflectance,
+            },
+            base_color_texture: material.base_color_texture.clone(),
+        }
+    }
+}
+
+impl Material for ClippedPropMaterial {
+    fn fragment_shader() -> ShaderRef {
+        "shaders/portal_clipped.wgsl".into()
+    }
+
+    fn specialize(
+        _pipeline: &bevy::pbr::MaterialPipeline<Self>,
+        descriptor: &mut bevy::render::render_resource::RenderPipelineDescriptor,
+        _layout: &bevy::render::mesh::MeshVertexBufferLayout,
+        _key: bevy::pbr::MaterialPipelineKey<Self>,
+    ) -> Result<(), bevy::render::render_resource::SpecializedMeshPipelineError> {
+        // Show the inside of the props where they are cut.
+        descriptor.primitive.cull_mode = None;
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone, ShaderType, Reflect)]
+pub struct ClippedPropUniform {
+    pub base_color: Color,
+    /// Plane in world space, the fragments behind it are discarded.
+    pub clip_plane: Vec4,
+    pub perceptual_roughness: f32,
+    pub metallic: f32,
+    pub reflectance: f32,
+}
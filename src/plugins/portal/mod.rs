@@ -5,8 +5,11 @@
 //!
 //! * The portal origin is at the center of the portal volume.
 //! * The portal clipping plane defined as the portal *back*.
+//! * Portals come in pairs sharing the same [`PortalLink`], each portal of a pair being one of its
+//!   two [`PortalEnd`]s.
+//! * A portal only opens when both portals of its pair exist and are active.
 
-use std::{f32::consts::FRAC_PI_4, time::Duration};
+use std::{fmt, time::Duration};
 
 use bevy::{
     math::{Vec3Swizzles, Vec4Swizzles},
@@ -20,30 +23,42 @@ use bevy::{
         view::RenderLayers,
     },
     transform::TransformSystem,
+    utils::{HashMap, HashSet},
 };
 use bevy_rapier3d::prelude::*;
+use leafwing_input_manager::prelude::ActionState;
 
 mod camera_projection;
+mod crossing;
+mod fizzler;
 mod geometry;
 mod material;
+mod raycast;
 
 use camera_projection::PortalCameraProjection;
+pub use fizzler::{restore_dissolved_prop, Dissolved, Dissolving, Fizzler};
 use material::*;
 use noise::{
     utils::{NoiseMapBuilder, PlaneMapBuilder},
     Fbm, Perlin,
 };
+pub use raycast::{PortalRayHit, PortalRaycast, RaySegment};
 
-use super::{first_person_controller::*, game::PlayerProgress, physics::*};
+use super::{
+    doors::Door,
+    first_person_controller::*,
+    game::{Ability, PlayerProgress},
+    input::Actions,
+    physics::*,
+};
 
 #[derive(Debug)]
 pub struct PortalPlugin;
 
 // TODO:
 //
-// * Transition between the open and closed materials depending on whether there are 1 or 2 portals open
 // * Figure where to place the portal cameras
-//   * Same thing for recursive portal iterations
+// * Show the portals of other pairs seen through a portal, they are only rendered by the main camera
 
 #[derive(Debug, StageLabel)]
 pub enum PortalStages {
@@ -54,7 +69,9 @@ pub enum PortalStages {
 pub enum PortalLabels {
     ShootPortals,
     UpdateMainCamera,
+    AllocatePairs,
     CreateCameras,
+    LinkPortals,
     SyncCameras,
     TeleportEntities,
     AnimateCamera,
@@ -64,18 +81,31 @@ impl Plugin for PortalPlugin {
     fn build(&self, app: &mut App) {
         app.add_plugin(MaterialPlugin::<OpenPortalMaterial>::default())
             .add_plugin(MaterialPlugin::<ClosedPortalMaterial>::default())
+            .add_plugin(MaterialPlugin::<ClippedPropMaterial>::default())
             .add_stage_before(
                 CoreStage::Update,
                 PortalStages::SpawnPortals,
                 SystemStage::parallel(),
             )
-            .register_type::<Portal<0>>()
-            .register_type::<Portal<1>>()
+            .register_type::<Portal>()
+            .register_type::<PortalLink>()
+            .register_type::<PortalEnd>()
+            .register_type::<LevelPortal>()
             .register_type::<PortalOrientation>()
             .register_type::<PortalResources>()
+            .register_type::<PortalRecursion>()
+            .init_resource::<PortalRecursion>()
+            .register_type::<PortalRenderQuality>()
+            .init_resource::<PortalRenderQuality>()
+            .register_type::<PortalExitPolicy>()
+            .init_resource::<PortalExitPolicy>()
             .register_type::<OpenPortalMaterial>()
             .register_type::<ClosedPortalMaterial>()
+            .register_type::<ClippedPropMaterial>()
             .register_type::<PortalTeleport>()
+            .register_type::<NonPortalable>()
+            .register_type::<Fizzler>()
+            .add_event::<PortalPlacementFailed>()
             .add_plugin(bevy::render::camera::CameraProjectionPlugin::<
                 PortalCameraProjection,
             >::default())
@@ -84,26 +114,44 @@ impl Plugin for PortalPlugin {
                 PortalStages::SpawnPortals,
                 update_main_camera.label(PortalLabels::UpdateMainCamera),
             )
-            .add_system_set_to_stage(
+            .add_system_to_stage(
                 PortalStages::SpawnPortals,
-                SystemSet::new()
-                    .label(PortalLabels::ShootPortals)
-                    .with_system(fire_portal::<0, 1>)
-                    .with_system(fire_portal::<1, 0>),
+                fire_portal.label(PortalLabels::ShootPortals),
+            )
+            .add_system_to_stage(
+                PortalStages::SpawnPortals,
+                log_portal_placement_failures.after(PortalLabels::ShootPortals),
+            )
+            .add_system_to_stage(
+                PortalStages::SpawnPortals,
+                allocate_portal_pairs
+                    .label(PortalLabels::AllocatePairs)
+                    .after(PortalLabels::ShootPortals),
             )
             .add_system_set_to_stage(
                 PortalStages::SpawnPortals,
                 SystemSet::new()
                     .label(PortalLabels::CreateCameras)
-                    .after(PortalLabels::ShootPortals)
+                    .after(PortalLabels::AllocatePairs)
                     .after(PortalLabels::UpdateMainCamera)
-                    .with_system(create_portal_cameras::<0>)
-                    .with_system(create_portal_cameras::<1>),
+                    .with_system(create_portal_cameras)
+                    .with_system(despawn_orphan_portal_cameras),
             )
-            .add_system(set_portal_materials)
+            .add_system_to_stage(
+                PortalStages::SpawnPortals,
+                apply_portal_recursion_depth.before(PortalLabels::AllocatePairs),
+            )
+            .add_system_to_stage(
+                PortalStages::SpawnPortals,
+                resize_portal_render_targets.before(PortalLabels::AllocatePairs),
+            )
+            .add_system(link_portals.label(PortalLabels::LinkPortals))
+            .add_system(set_portal_materials.after(PortalLabels::LinkPortals))
+            .add_system(sync_portal_camera_projections.before(PortalLabels::SyncCameras))
             .add_system_set(
                 SystemSet::new()
                     .label(PortalLabels::SyncCameras)
+                    .after(PortalLabels::LinkPortals)
                     .with_system(sync_portal_cameras),
             )
             .add_system(
@@ -116,6 +164,10 @@ impl Plugin for PortalPlugin {
                     .label(PortalLabels::TeleportEntities)
                     .after(PortalLabels::SyncCameras),
             )
+            .add_system(crossing::update_portal_clones.after(PortalLabels::TeleportEntities))
+            .add_system(crossing::despawn_orphan_portal_clones)
+            .add_system(fizzler::fizzle_on_sensor_contact.after(PortalLabels::TeleportEntities))
+            .add_system(fizzler::animate_dissolve)
             .add_system(apply_portal_attraction.after(PortalLabels::TeleportEntities))
             .add_system(apply_portal_repulsion.after(PortalLabels::TeleportEntities))
             .add_system(
@@ -132,44 +184,73 @@ impl Plugin for PortalPlugin {
 }
 
 impl PortalPlugin {
-    fn spawn_portal<const N: u32>(
+    #[allow(clippy::too_many_arguments)]
+    fn spawn_portal(
         commands: &mut Commands,
         player_transform: &GlobalTransform,
-        portal_query: &Query<(&Portal<N>, Entity)>,
-        other_portal_entity: Option<Entity>,
-        rapier: &Res<RapierContext>,
+        portal_query: &Query<(&Portal, &GlobalTransform, Entity)>,
+        bodies_query: &Query<(Option<&RigidBody>, Option<&Door>, Option<&Parent>)>,
+        groups_query: &Query<&CollisionGroups>,
+        end: PortalEnd,
+        raycast: &PortalRaycast,
         portal_res: &Res<PortalResources>,
-    ) -> Option<Entity> {
-        let (_entity, impact) = rapier.cast_ray_and_get_normal(
+    ) -> Result<Entity, PortalPlacementFailure> {
+        // Moving bodies and non portalable surfaces are hit too, so the portal isn't placed on the
+        // surface behind them.
+        let ray = raycast.cast_ray(
             player_transform.translation(),
             player_transform.forward(),
             Real::MAX,
             true,
-            QueryFilter::only_fixed().groups(InteractionGroups::new(
+            QueryFilter::new().groups(InteractionGroups::new(
                 RAYCAST_GROUP.bits().into(),
                 (WALLS_GROUP | GROUND_GROUP).bits().into(),
             )),
-        )?;
+        );
+        let (surface, impact) = ray.hit.ok_or(PortalPlacementFailure::NoSurface)?;
+        // The shot may have gone through portals.
+        let direction = ray
+            .last_segment()
+            .map_or(player_transform.forward(), |segment| segment.direction);
+        let rapier = &raycast.rapier;
+        if groups_query.get(surface).map_or(false, |groups| {
+            groups.memberships.contains(NON_PORTALABLE_GROUP)
+        }) {
+            return Err(PortalPlacementFailure::NonPortalableSurface);
+        }
+        if geometry::is_moving_body(surface, bodies_query) {
+            return Err(PortalPlacementFailure::MovingSurface);
+        }
 
-        if let Ok((previous_portal, entity)) = portal_query.get_single() {
+        let portal = PortalBundle::from_ray_impact(impact, direction, end, portal_res, rapier);
+        let transform = &portal.mesh_bundle.transform;
+        let normal = transform.back();
+        let center = transform.translation + normal * PORTAL_MESH_DEPTH;
+        geometry::check_portal_fits(center, normal, transform.up(), surface, rapier)?;
+
+        let previous_portal = portal_query
+            .iter()
+            .find(|(portal, _, _)| portal.link == PortalLink::Player && portal.end == end);
+        let previous_entity = previous_portal.map(|(_, _, entity)| entity);
+        if portal_query.iter().any(|(_, other_transform, entity)| {
+            Some(entity) != previous_entity
+                && geometry::overlaps_portal(center, normal, transform.up(), other_transform)
+        }) {
+            return Err(PortalPlacementFailure::OverlapsPortal);
+        }
+
+        if let Some((previous_portal, _, entity)) = previous_portal {
             info!("Despawning previous portal");
-            if let Some(cam) = previous_portal.camera {
-                commands.entity(cam).despawn_recursive();
+            for cam in &previous_portal.cameras {
+                commands.entity(*cam).despawn_recursive();
             }
             commands.entity(entity).despawn_recursive();
         }
-        let portal = PortalBundle::<N>::from_ray_impact(
-            impact,
-            player_transform,
-            portal_res,
-            other_portal_entity,
-            rapier,
-        );
         info!(
             "Spawning portal at {}",
             &portal.mesh_bundle.transform.translation
         );
-        Some(commands.spawn(portal).id())
+        Ok(commands.spawn(portal).id())
     }
 
     fn get_portal_plane(trf: &GlobalTransform) -> Vec4 {
@@ -182,15 +263,165 @@ impl PortalPlugin {
 #[derive(Debug, Default, Reflect, Resource)]
 pub struct PortalResources {
     noise_texture: Handle<Image>,
-    render_targets: [Handle<Image>; 2],
-    open_materials: [Handle<OpenPortalMaterial>; 2],
-    closed_materials: [Handle<ClosedPortalMaterial>; 2],
+    /// Render targets and materials of every portal pair, allocated when a pair appears.
+    pairs: HashMap<PortalLink, PortalPairResources>,
     portal_mesh: Handle<Mesh>,
+    /// Size of the render targets, following the window size and the render quality.
+    render_target_size: UVec2,
     main_camera: Option<Entity>,
     dbg_sphere_mesh: Handle<Mesh>,
     dbg_material: Handle<StandardMaterial>,
 }
 
+/// Number of times portals seen through portals are rendered, before they are shown closed.
+///
+/// Every recursion level adds a camera and a render target per portal.
+#[derive(Debug, Reflect, Resource)]
+pub struct PortalRecursion {
+    pub depth: usize,
+}
+
+impl Default for PortalRecursion {
+    fn default() -> Self {
+        PortalRecursion { depth: 2 }
+    }
+}
+
+impl PortalRecursion {
+    /// Return the recursion depth, clamped so the render layers of every level are available.
+    pub fn depth(&self) -> usize {
+        self.depth.clamp(1, MAX_PORTAL_RECURSION_DEPTH)
+    }
+}
+
+/// Resolution of the portal render targets, relative to the window resolution.
+#[derive(Debug, Reflect, Resource)]
+pub struct PortalRenderQuality {
+    pub resolution_scale: f32,
+}
+
+impl Default for PortalRenderQuality {
+    fn default() -> Self {
+        // Rendering every portal at full resolution is too slow in the browser.
+        #[cfg(target_family = "wasm")]
+        let resolution_scale = 0.5;
+        #[cfg(not(target_family = "wasm"))]
+        let resolution_scale = 1.;
+        PortalRenderQuality { resolution_scale }
+    }
+}
+
+impl PortalRenderQuality {
+    /// Return the size of the portal render targets for the given window.
+    pub fn render_target_size(&self, window: &Window) -> UVec2 {
+        let window_size = UVec2::new(window.physical_width(), window.physical_height()).as_vec2();
+        (window_size * self.resolution_scale.clamp(0.1, 1.))
+            .round()
+            .as_uvec2()
+            .max(UVec2::ONE)
+    }
+}
+
+/// Portals seen at depth 1 are on layer 1, the main camera renders them. Every pair uses the next
+/// layers for its deeper levels, the last one being reserved for the closed portals of the deepest
+/// level.
+const MAX_PORTAL_RECURSION_DEPTH: usize = RenderLayers::TOTAL_LAYERS - 2;
+
+/// Return the render layer of the portals of a pair seen at the given recursion depth, above 1, so
+/// the portal cameras only see the portals of their own pair.
+///
+/// Once every layer is used, the next pairs share the layers of the first ones.
+fn recursion_layer(pair_index: usize, depth: usize, max_depth: usize) -> u8 {
+    let pair_slots = MAX_PORTAL_RECURSION_DEPTH / max_depth;
+    (2 + (pair_index % pair_slots) * max_depth + depth - 2) as u8
+}
+
+/// Offset between the priorities of the portal cameras of two recursion levels.
+const PORTAL_CAMERAS_PER_DEPTH: usize = 1000;
+
+/// Render resources of a pair of portals, indexed by [`PortalEnd`].
+#[derive(Debug, Default, Clone, Reflect, FromReflect)]
+pub struct PortalPairResources {
+    /// Index of the pair, used to order the portal cameras.
+    index: usize,
+    /// Render targets and materials showing them, for every recursion level.
+    render_targets: Vec<[Handle<Image>; 2]>,
+    open_materials: Vec<[Handle<OpenPortalMaterial>; 2]>,
+    closed_materials: [Handle<ClosedPortalMaterial>; 2],
+}
+
+impl PortalPairResources {
+    fn new(
+        index: usize,
+        depth: usize,
+        noise_texture: &Handle<Image>,
+        render_target_size: UVec2,
+        images: &mut Assets<Image>,
+        open_materials: &mut Assets<OpenPortalMaterial>,
+        closed_materials: &mut Assets<ClosedPortalMaterial>,
+    ) -> PortalPairResources {
+        let mut pair = PortalPairResources { index, ..default() };
+        for _ in 0..depth {
+            let render_targets =
+                [(); 2].map(|_| images.add(create_render_target(render_target_size)));
+            let materials = render_targets
+                .clone()
+                .map(|texture| open_materials.add(OpenPortalMaterial { texture }));
+            pair.render_targets.push(render_targets);
+            pair.open_materials.push(materials);
+        }
+        for end in [PortalEnd::A, PortalEnd::B] {
+            let i = end.index();
+            pair.closed_materials[i] = closed_materials.add(ClosedPortalMaterial {
+                texture: noise_texture.clone(),
+                uniform: ClosedPortalUniform { color: end.color() },
+            });
+        }
+        pair
+    }
+}
+
+/// Why a portal shot by the player could not be placed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PortalPlacementFailure {
+    /// The shot didn't hit any portal surface.
+    NoSurface,
+    /// The surface is tagged as refusing portals.
+    NonPortalableSurface,
+    /// The surface belongs to a body moved by physics or an animation.
+    MovingSurface,
+    /// The portal doesn't fit on the surface, or something sticks out of it.
+    SurfaceTooSmall,
+    /// The portal would span several surfaces, or a surface which isn't flat.
+    UnevenSurface,
+    /// The portal would overlap another portal.
+    OverlapsPortal,
+}
+
+impl fmt::Display for PortalPlacementFailure {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            PortalPlacementFailure::NoSurface => write!(f, "no surface to place the portal on"),
+            PortalPlacementFailure::NonPortalableSurface => {
+                write!(f, "portals can not be placed on the surface")
+            }
+            PortalPlacementFailure::MovingSurface => write!(f, "the surface is moving"),
+            PortalPlacementFailure::SurfaceTooSmall => {
+                write!(f, "the portal doesn't fit on the surface")
+            }
+            PortalPlacementFailure::UnevenSurface => write!(f, "the surface isn't flat"),
+            PortalPlacementFailure::OverlapsPortal => write!(f, "another portal is in the way"),
+        }
+    }
+}
+
+/// Event sent when a portal shot by the player is rejected, for the HUD and audio to react to.
+#[derive(Debug, Clone)]
+pub struct PortalPlacementFailed {
+    pub end: PortalEnd,
+    pub reason: PortalPlacementFailure,
+}
+
 #[derive(Debug, Default, Clone, Reflect)]
 /// Enumerates the different cases for portal orientation that we handle differently.
 pub enum PortalOrientation {
@@ -201,26 +432,103 @@ pub enum PortalOrientation {
     Other,
 }
 
-#[derive(Debug, Default, Component, Reflect)]
+/// Identifies a pair of linked portals.
+#[derive(Debug, Default, Clone, PartialEq, Eq, Hash, Reflect, FromReflect)]
+pub enum PortalLink {
+    /// The portals shot by the player.
+    #[default]
+    Player,
+    /// Portals placed in the level, paired by their `portal_link` node extra.
+    Level(String),
+}
+
+/// Which end of its pair a portal is.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Hash, Reflect, FromReflect)]
+pub enum PortalEnd {
+    #[default]
+    A,
+    B,
+}
+
+impl PortalEnd {
+    pub const fn index(self) -> usize {
+        match self {
+            PortalEnd::A => 0,
+            PortalEnd::B => 1,
+        }
+    }
+
+    pub const fn other(self) -> PortalEnd {
+        match self {
+            PortalEnd::A => PortalEnd::B,
+            PortalEnd::B => PortalEnd::A,
+        }
+    }
+
+    /// Return the action shooting this end of the player portals.
+    pub const fn action(self) -> Actions {
+        match self {
+            PortalEnd::A => Actions::ShootA,
+            PortalEnd::B => Actions::ShootB,
+        }
+    }
+
+    /// Return the ability needed to shoot this end of the player portals.
+    pub const fn ability(self) -> Ability {
+        match self {
+            PortalEnd::A => Ability::PortalA,
+            PortalEnd::B => Ability::PortalB,
+        }
+    }
+
+    /// Return the color of the portal while it is closed.
+    pub fn color(self) -> Color {
+        match self {
+            // Orange
+            PortalEnd::A => Color::rgba(1., 0.7, 0.2, 1.),
+            // Blue
+            PortalEnd::B => Color::rgba(0.2, 0.78, 1., 1.),
+        }
+    }
+}
+
+#[derive(Debug, Component, Reflect)]
 #[reflect(Component)]
-pub struct Portal<const N: u32> {
-    /// The camera which is used to render to the texture applied to this portal
-    /// This camera is positioned to look at the other portal from behind, with the same relative
+pub struct Portal {
+    /// The cameras which are used to render to the textures applied to this portal, one per
+    /// recursion level.
+    /// These cameras are positioned to look at the other portal from behind, with the same relative
     /// position.
-    pub camera: Option<Entity>,
+    pub cameras: Vec<Entity>,
+    pub link: PortalLink,
+    pub end: PortalEnd,
+    /// Inactive portals stay closed, as if the other portal of the pair didn't exist.
+    pub active: bool,
+    /// Color of the portal while closed, overriding the default color of its end.
+    pub color: Option<Color>,
+    /// The other portal of the pair, if both exist and are active.
     linked_portal: Option<Entity>,
     orientation: PortalOrientation,
 }
 
-impl<const N: u32> Portal<N> {
-    /// Return the mouse button associated to shooting this portal type.
-    pub const fn mouse_button() -> MouseButton {
-        match N {
-            0 => MouseButton::Left,
-            1 => MouseButton::Right,
-            _ => panic!("No such portal"),
+impl Default for Portal {
+    fn default() -> Self {
+        Portal {
+            cameras: Vec::new(),
+            link: default(),
+            end: default(),
+            active: true,
+            color: None,
+            linked_portal: None,
+            orientation: default(),
         }
     }
+}
+
+impl Portal {
+    pub fn linked_portal(&self) -> Option<Entity> {
+        self.linked_portal
+    }
 
     /// Return the collision groups filter which turns off collisions with this portal's surface.
     pub fn filter_collisions(&self) -> Group {
@@ -238,13 +546,98 @@ impl<const N: u32> Portal<N> {
     }
 }
 
-#[derive(Debug, Default, Component, Reflect, FromReflect)]
-pub struct PortalCamera<const N: u32>;
+/// Despawn the portals shot by the player, along with their cameras.
+pub fn close_player_portals(commands: &mut Commands, portal_query: &Query<(&Portal, Entity)>) {
+    for (portal, entity) in portal_query {
+        if portal.link == PortalLink::Player {
+            for camera in &portal.cameras {
+                commands.entity(*camera).despawn_recursive();
+            }
+            commands.entity(entity).despawn_recursive();
+        }
+    }
+}
+
+/// Level node on which a fixed portal is spawned, linked to the other node with the same link.
+#[derive(Debug, Default, Clone, Component, Reflect, FromReflect)]
+#[reflect(Component)]
+pub struct LevelPortal {
+    pub link: String,
+    pub color: Option<Color>,
+    pub active: bool,
+}
+
+/// Camera rendering the view through a portal, at the given recursion depth.
+#[derive(Debug, Component, Reflect, FromReflect)]
+pub struct PortalCamera {
+    pub portal: Entity,
+    pub depth: usize,
+}
+
+/// Portal mesh seen by the portal cameras of the previous recursion depth.
+#[derive(Debug, Component, Reflect, FromReflect)]
+pub struct PortalRecursionMesh {
+    pub depth: usize,
+}
 
 #[derive(Debug, Component, Clone, Default, Reflect, FromReflect)]
 #[reflect(Component)]
 pub struct PortalTeleport;
 
+/// Speed along the exit portal normal given to the entities exiting with
+/// [`PortalExitPolicy::ForceNormal`], so they don't fall back into the portal.
+const MIN_OUTBOUND_SPEED: f32 = 3.;
+
+/// How the velocity of an entity is mapped when it goes through a portal.
+///
+/// The resource is the policy of every entity, unless it has its own policy component.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Component, Resource, Reflect, FromReflect)]
+#[reflect(Component)]
+pub enum PortalExitPolicy {
+    /// The velocity is transformed from the entry portal to the exit portal.
+    #[default]
+    PreserveRotated,
+    /// The speed is kept, but the entity exits along the exit portal normal.
+    ForceNormal,
+    /// The velocity is transformed, with its speed clamped between a minimum and a maximum.
+    Clamp(f32, f32),
+}
+
+impl PortalExitPolicy {
+    /// Return the velocity of an entity exiting the portal with the given normal.
+    pub fn exit_velocity(
+        &self,
+        portal_to_linked: &Transform,
+        exit_normal: Vec3,
+        velocity: Vec3,
+    ) -> Vec3 {
+        let rotated = portal_to_linked.rotation.mul_vec3(velocity);
+        match *self {
+            PortalExitPolicy::PreserveRotated => rotated,
+            PortalExitPolicy::ForceNormal => {
+                let mut exit_velocity = exit_normal * rotated.length();
+                if exit_velocity.dot(exit_normal) < MIN_OUTBOUND_SPEED {
+                    exit_velocity += MIN_OUTBOUND_SPEED * exit_normal;
+                }
+                exit_velocity
+            }
+            PortalExitPolicy::Clamp(min, max) => {
+                let min = min.min(max);
+                if rotated.length_squared() < f32::EPSILON {
+                    exit_normal * min
+                } else {
+                    rotated.clamp_length(min, max)
+                }
+            }
+        }
+    }
+}
+
+/// Level geometry tagged with `portalable: "false"`, on which portals can not be placed.
+#[derive(Debug, Component, Clone, Default, Reflect, FromReflect)]
+#[reflect(Component)]
+pub struct NonPortalable;
+
 #[derive(Debug, Component, Clone, Default, Reflect, FromReflect)]
 #[reflect(Component)]
 pub struct AnimateRoll {
@@ -267,18 +660,18 @@ impl AnimateRoll {
 }
 
 #[derive(Bundle)]
-pub struct PortalBundle<const N: u32> {
+pub struct PortalBundle {
     #[bundle]
     mesh_bundle: MaterialMeshBundle<OpenPortalMaterial>,
     render_layers: RenderLayers,
-    portal: Portal<N>,
+    portal: Portal,
     collider: Collider,
     active_events: ActiveEvents,
     sensor: Sensor,
     collision_groups: CollisionGroups,
 }
 
-impl<const N: u32> Default for PortalBundle<N> {
+impl Default for PortalBundle {
     fn default() -> Self {
         PortalBundle {
             render_layers: RenderLayers::layer(1),
@@ -292,14 +685,14 @@ impl<const N: u32> Default for PortalBundle<N> {
     }
 }
 
-impl<const N: u32> PortalBundle<N> {
+impl PortalBundle {
     fn from_ray_impact(
         impact: RayIntersection,
-        player_transform: &GlobalTransform,
+        ray_direction: Vec3,
+        end: PortalEnd,
         portal_res: &Res<PortalResources>,
-        other_portal: Option<Entity>,
         rapier: &Res<RapierContext>,
-    ) -> PortalBundle<N> {
+    ) -> PortalBundle {
         const Z_FIGHTING_OFFSET: f32 = 0.001;
         // We place the portal at the ray intersection point, plus a small offset
         // along the surface normal to prevent Z fighting.
@@ -312,13 +705,10 @@ impl<const N: u32> PortalBundle<N> {
             ..default()
         };
         let (up, orientation) = if impact.normal.abs().abs_diff_eq(Vec3::Y, 0.001) {
-            // If the normal is close to vertical, align the up direction with the player forward
-            // direction.
-            let forward_to_normal = player_transform
-                .forward()
-                .project_onto_normalized(impact.normal);
+            // If the normal is close to vertical, align the up direction with the shot direction.
+            let forward_to_normal = ray_direction.project_onto_normalized(impact.normal);
             (
-                (player_transform.forward() - forward_to_normal).normalize(),
+                (ray_direction - forward_to_normal).normalize(),
                 PortalOrientation::Horizontal,
             )
         } else {
@@ -340,12 +730,54 @@ impl<const N: u32> PortalBundle<N> {
         PortalBundle {
             mesh_bundle: MaterialMeshBundle {
                 mesh: portal_res.portal_mesh.clone(),
-                material: portal_res.open_materials[N as usize].clone(),
+                material: portal_res
+                    .pairs
+                    .get(&PortalLink::Player)
+                    .map(|pair| pair.open_materials[0][end.index()].clone())
+                    .unwrap_or_default(),
                 transform: offset_portal,
                 ..default()
             },
-            portal: Portal::<N> {
-                linked_portal: other_portal,
+            portal: Portal {
+                link: PortalLink::Player,
+                end,
+                orientation,
+                ..default()
+            },
+            ..default()
+        }
+    }
+
+    /// Create a portal to be spawned as a child of a level node, on the surface facing the node's
+    /// local Z axis.
+    pub fn from_level_node(
+        level_portal: &LevelPortal,
+        end: PortalEnd,
+        node_transform: &Transform,
+        portal_res: &PortalResources,
+    ) -> PortalBundle {
+        // Level nodes are expected to be at the scene root, so their local orientation is their
+        // world orientation.
+        let orientation = if node_transform.back().abs().abs_diff_eq(Vec3::Y, 0.001) {
+            PortalOrientation::Horizontal
+        } else {
+            PortalOrientation::Other
+        };
+
+        // Offset the portal so the clipping plane coincides with the node origin.
+        let mut offset_portal = Transform::from_scale(Vec3::splat(2.));
+        offset_portal.translation += offset_portal.forward() * PORTAL_MESH_DEPTH;
+        PortalBundle {
+            mesh_bundle: MaterialMeshBundle {
+                mesh: portal_res.portal_mesh.clone(),
+                transform: offset_portal,
+                ..default()
+            },
+            portal: Portal {
+                link: PortalLink::Level(level_portal.link.clone()),
+                end,
+                active: level_portal.active,
+                color: level_portal.color,
                 orientation,
                 ..default()
             },
@@ -356,12 +788,39 @@ impl<const N: u32> PortalBundle<N> {
 
 const PORTAL_MESH_DEPTH: f32 = 0.5;
 
+/// Create an image portal cameras can render to.
+fn create_render_target(size: UVec2) -> Image {
+    let tex_size = Extent3d {
+        width: size.x,
+        height: size.y,
+        ..default()
+    };
+    #[cfg(target_family = "wasm")]
+    let texture_format = TextureFormat::Rgba8UnormSrgb;
+    #[cfg(not(target_family = "wasm"))]
+    let texture_format = TextureFormat::Bgra8UnormSrgb;
+    let mut image = Image {
+        texture_descriptor: TextureDescriptor {
+            label: None,
+            size: tex_size,
+            mip_level_count: 1,
+            sample_count: 1,
+            dimension: TextureDimension::D2,
+            format: texture_format,
+            usage: TextureUsages::TEXTURE_BINDING
+                | TextureUsages::COPY_DST
+                | TextureUsages::RENDER_ATTACHMENT,
+        },
+        ..default()
+    };
+    image.resize(tex_size);
+    image
+}
+
 /// Load the assets required to render the portals.
 fn load_portal_assets(
     mut commands: Commands,
     mut meshes: ResMut<Assets<Mesh>>,
-    mut materials: ResMut<Assets<OpenPortalMaterial>>,
-    mut closed_materials: ResMut<Assets<ClosedPortalMaterial>>,
     mut std_materials: ResMut<Assets<StandardMaterial>>,
     mut images: ResMut<Assets<Image>>,
 ) {
@@ -414,56 +873,6 @@ fn load_portal_assets(
     //::new(, TextureDimension::D2, buf, TextureFormat::R8Unorm);
     let noise_texture = images.add(noise_image);
 
-    let mut open_materials: [Handle<OpenPortalMaterial>; 2] = default();
-    let mut closed_mats: [Handle<ClosedPortalMaterial>; 2] = default();
-    closed_mats[0] = closed_materials.add(ClosedPortalMaterial {
-        texture: noise_texture.clone(),
-        // Orange
-        uniform: ClosedPortalUniform {
-            color: Color::rgba(1., 0.7, 0.2, 1.),
-        },
-    });
-    closed_mats[1] = closed_materials.add(ClosedPortalMaterial {
-        texture: noise_texture.clone(),
-        // Blue
-        uniform: ClosedPortalUniform {
-            color: Color::rgba(0.2, 0.78, 1., 1.),
-        },
-    });
-
-    let mut render_targets: [Handle<Image>; 2] = default();
-    for i in 0..2 {
-        let tex_size = Extent3d {
-            width: 1280,
-            height: 720,
-            ..default()
-        };
-        #[cfg(target_family = "wasm")]
-        let texture_format = TextureFormat::Rgba8UnormSrgb;
-        #[cfg(not(target_family = "wasm"))]
-        let texture_format = TextureFormat::Bgra8UnormSrgb;
-        let mut image = Image {
-            texture_descriptor: TextureDescriptor {
-                label: None,
-                size: tex_size,
-                mip_level_count: 1,
-                sample_count: 1,
-                dimension: TextureDimension::D2,
-                format: texture_format,
-                usage: TextureUsages::TEXTURE_BINDING
-                    | TextureUsages::COPY_DST
-                    | TextureUsages::RENDER_ATTACHMENT,
-            },
-            ..default()
-        };
-        image.resize(tex_size);
-        render_targets[i] = images.add(image);
-
-        open_materials[i] = materials.add(OpenPortalMaterial {
-            texture: render_targets[i].clone(),
-        });
-    }
-
     let dbg_mesh = meshes.add(
         shape::UVSphere {
             radius: 0.5,
@@ -475,10 +884,10 @@ fn load_portal_assets(
     let dbg_mat = std_materials.add(Color::PURPLE.into());
 
     commands.insert_resource(PortalResources {
-        render_targets,
-        open_materials,
-        closed_materials: closed_mats,
+        pairs: HashMap::new(),
         portal_mesh,
+        // Replaced by the window size as soon as the window exists.
+        render_target_size: UVec2::new(1280, 720),
         main_camera: None,
         dbg_sphere_mesh: dbg_mesh,
         dbg_material: dbg_mat,
@@ -508,261 +917,466 @@ fn update_main_camera(
     }
 }
 
-/// On left click/right click, shoot a portal.
-#[allow(clippy::too_many_arguments, clippy::collapsible_if)]
-fn fire_portal<const N: u32, const OTHER: u32>(
+/// Shoot the portals whose action was just pressed, if the player unlocked them.
+#[allow(clippy::too_many_arguments)]
+fn fire_portal(
     mut commands: Commands,
+    input_query: Query<&ActionState<Actions>, With<FirstPersonController>>,
     player_query: Query<&GlobalTransform, With<FirstPersonCamera>>,
-    portal_query: Query<(&Portal<N>, Entity)>,
-    other_portal_query: Query<Entity, With<Portal<OTHER>>>,
-    rapier: Res<RapierContext>,
-    mouse_buttons: Res<Input<MouseButton>>,
+    portal_query: Query<(&Portal, &GlobalTransform, Entity)>,
+    bodies_query: Query<(Option<&RigidBody>, Option<&Door>, Option<&Parent>)>,
+    groups_query: Query<&CollisionGroups>,
+    raycast: PortalRaycast,
     portal_res: Res<PortalResources>,
     progress: Res<PlayerProgress>,
+    mut placement_failed: EventWriter<PortalPlacementFailed>,
 ) {
-    if let Ok(player_pos) = player_query.get_single() {
-        if *progress != PlayerProgress::GettingStarted {
-            if mouse_buttons.just_pressed(Portal::<N>::mouse_button()) {
-                info!("Shooting portal {}", N);
-                PortalPlugin::spawn_portal(
+    if let (Ok(input), Ok(player_pos)) = (input_query.get_single(), player_query.get_single()) {
+        for end in [PortalEnd::A, PortalEnd::B] {
+            // Each portal is unlocked on its own.
+            if input.just_pressed(end.action()) && progress.has(end.ability()) {
+                info!("Shooting portal {:?}", end);
+                if let Err(reason) = PortalPlugin::spawn_portal(
                     &mut commands,
                     player_pos,
                     &portal_query,
-                    other_portal_query.get_single().ok(),
-                    &rapier,
+                    &bodies_query,
+                    &groups_query,
+                    end,
+                    &raycast,
                     &portal_res,
-                );
+                ) {
+                    placement_failed.send(PortalPlacementFailed { end, reason });
+                }
+            }
+        }
+    }
+}
+
+/// Report the portals shot by the player which could not be placed.
+fn log_portal_placement_failures(mut placement_failed: EventReader<PortalPlacementFailed>) {
+    for failure in placement_failed.iter() {
+        info!("Can not place portal {:?}: {}", failure.end, failure.reason);
+    }
+}
+
+/// Allocate the render resources of new portal pairs, and free those of the pairs which are gone.
+fn allocate_portal_pairs(
+    portal_query: Query<&Portal>,
+    mut portal_res: ResMut<PortalResources>,
+    mut images: ResMut<Assets<Image>>,
+    mut open_materials: ResMut<Assets<OpenPortalMaterial>>,
+    mut closed_materials: ResMut<Assets<ClosedPortalMaterial>>,
+    recursion: Res<PortalRecursion>,
+) {
+    let links = portal_query
+        .iter()
+        .map(|portal| &portal.link)
+        .collect::<HashSet<_>>();
+    // The player portals are shot all the time, keep their resources around.
+    portal_res
+        .pairs
+        .retain(|link, _| *link == PortalLink::Player || links.contains(link));
+
+    for link in links {
+        if !portal_res.pairs.contains_key(link) {
+            let used_indices = portal_res
+                .pairs
+                .values()
+                .map(|pair| pair.index)
+                .collect::<HashSet<_>>();
+            let index = (0..).find(|i| !used_indices.contains(i)).unwrap();
+            let pair = PortalPairResources::new(
+                index,
+                recursion.depth(),
+                &portal_res.noise_texture,
+                portal_res.render_target_size,
+                &mut images,
+                &mut open_materials,
+                &mut closed_materials,
+            );
+            portal_res.pairs.insert(link.clone(), pair);
+        }
+    }
+
+    // Apply the colors authored on the portals to the closed materials.
+    for portal in &portal_query {
+        let color = portal.color.unwrap_or_else(|| portal.end.color());
+        if let Some(pair) = portal_res.pairs.get(&portal.link) {
+            let handle = &pair.closed_materials[portal.end.index()];
+            if closed_materials
+                .get(handle)
+                .map_or(false, |material| material.uniform.color != color)
+            {
+                closed_materials.get_mut(handle).unwrap().uniform.color = color;
             }
         }
     }
 }
 
-fn create_portal_cameras<const N: u32>(
+fn create_portal_cameras(
     mut commands: Commands,
-    mut portal_query: Query<&mut Portal<N>>,
+    mut portal_query: Query<(&mut Portal, Entity)>,
+    main_camera_query: Query<&Projection, With<FirstPersonCamera>>,
     portal_res: Res<PortalResources>,
+    recursion: Res<PortalRecursion>,
 ) {
-    if let Ok(mut portal) = portal_query.get_single_mut() {
-        if portal.camera.is_none() && portal_res.main_camera.is_some() {
-            portal.camera = Some(
-                commands
+    let main_projection = match portal_res
+        .main_camera
+        .and_then(|camera| main_camera_query.get(camera).ok())
+    {
+        Some(Projection::Perspective(projection)) => projection,
+        _ => return,
+    };
+    for (mut portal, entity) in &mut portal_query {
+        if !portal.cameras.is_empty() {
+            continue;
+        }
+        if let Some(pair) = portal_res.pairs.get(&portal.link) {
+            let end = portal.end.index();
+            for depth in 1..=recursion.depth() {
+                let camera = commands
                     .spawn(Camera3dBundle {
                         camera: Camera {
-                            // Render before the main camera.
-                            priority: -1 - N as isize,
+                            // Render before the main camera, and deeper recursion levels first.
+                            priority: -((depth * PORTAL_CAMERAS_PER_DEPTH
+                                + 2 * pair.index
+                                + end
+                                + 1) as isize),
                             target: RenderTarget::Image(
-                                portal_res.render_targets[N as usize].clone(),
+                                pair.render_targets[depth - 1][end].clone(),
                             ),
                             ..default()
                         },
                         ..default()
                     })
                     .insert(PortalCameraProjection {
-                        fov: FRAC_PI_4,
-                        aspect_ratio: 16. / 9.,
+                        fov: main_projection.fov,
+                        aspect_ratio: main_projection.aspect_ratio,
+                        far: main_projection.far,
                         ..default()
                     })
-                    .insert(PortalCamera::<N>)
+                    .insert(PortalCamera {
+                        portal: entity,
+                        depth,
+                    })
+                    // Only see the portals of the pair showing the next recursion level.
+                    .insert(RenderLayers::layer(0).with(recursion_layer(
+                        pair.index,
+                        depth + 1,
+                        recursion.depth(),
+                    )))
                     .remove::<Projection>()
                     .insert(VisibilityBundle {
                         visibility: Visibility::VISIBLE,
                         ..default()
                     })
-                    .id(),
-            );
+                    .id();
+                portal.cameras.push(camera);
+
+                // The portal as seen by the cameras of the previous recursion level.
+                commands.entity(entity).with_children(|parent| {
+                    parent.spawn((
+                        MaterialMeshBundle::<ClosedPortalMaterial> {
+                            mesh: portal_res.portal_mesh.clone(),
+                            material: pair.closed_materials[end].clone(),
+                            ..default()
+                        },
+                        RenderLayers::layer(recursion_layer(
+                            pair.index,
+                            depth + 1,
+                            recursion.depth(),
+                        )),
+                        PortalRecursionMesh { depth: depth + 1 },
+                    ));
+                });
+            }
         }
     }
 }
 
-fn set_portal_materials(
+/// Resize the render targets of the portals when the window is resized or the quality changes.
+fn resize_portal_render_targets(
+    windows: Res<Windows>,
+    quality: Res<PortalRenderQuality>,
+    mut portal_res: ResMut<PortalResources>,
+    mut images: ResMut<Assets<Image>>,
+) {
+    let size = match windows.get_primary() {
+        Some(window) => quality.render_target_size(window),
+        None => return,
+    };
+    if size == portal_res.render_target_size {
+        return;
+    }
+    info!("Resizing portal render targets to {}x{}", size.x, size.y);
+    portal_res.render_target_size = size;
+    let extent = Extent3d {
+        width: size.x,
+        height: size.y,
+        ..default()
+    };
+    for pair in portal_res.pairs.values() {
+        for handle in pair.render_targets.iter().flatten() {
+            if let Some(image) = images.get_mut(handle) {
+                image.resize(extent);
+            }
+        }
+    }
+}
+
+/// Reallocate the portal cameras and render targets when the recursion depth changes.
+fn apply_portal_recursion_depth(
     mut commands: Commands,
-    portal_a_query: Query<Entity, (With<Portal<0>>, Without<Portal<1>>)>,
-    portal_b_query: Query<Entity, (With<Portal<1>>, Without<Portal<0>>)>,
-    resources: Res<PortalResources>,
+    mut portal_query: Query<&mut Portal>,
+    recursion_meshes_query: Query<Entity, With<PortalRecursionMesh>>,
+    mut portal_res: ResMut<PortalResources>,
+    recursion: Res<PortalRecursion>,
 ) {
-    match (portal_a_query.get_single(), portal_b_query.get_single()) {
-        (Ok(portal_a), Ok(portal_b)) => {
-            commands
-                .entity(portal_a)
-                .remove::<Handle<ClosedPortalMaterial>>()
-                .insert(resources.open_materials[0].clone());
-            commands
-                .entity(portal_b)
-                .remove::<Handle<ClosedPortalMaterial>>()
-                .insert(resources.open_materials[1].clone());
+    if recursion.is_changed() && !recursion.is_added() {
+        info!("Portal recursion depth set to {}", recursion.depth());
+        for mut portal in &mut portal_query {
+            for camera in portal.cameras.drain(..) {
+                commands.entity(camera).despawn_recursive();
+            }
         }
-        (Ok(portal_a), Err(_)) => {
-            commands
-                .entity(portal_a)
-                .remove::<Handle<OpenPortalMaterial>>()
-                .insert(resources.closed_materials[0].clone());
+        for entity in &recursion_meshes_query {
+            commands.entity(entity).despawn_recursive();
         }
-        (Err(_), Ok(portal_b)) => {
-            commands
-                .entity(portal_b)
-                .remove::<Handle<OpenPortalMaterial>>()
-                .insert(resources.closed_materials[1].clone());
+        portal_res.pairs.clear();
+    }
+}
+
+/// Despawn the cameras of portals which were despawned along with their level.
+fn despawn_orphan_portal_cameras(
+    mut commands: Commands,
+    cameras_query: Query<(&PortalCamera, Entity)>,
+    portal_query: Query<(), With<Portal>>,
+) {
+    for (camera, entity) in &cameras_query {
+        if portal_query.get(camera.portal).is_err() {
+            commands.entity(entity).despawn_recursive();
         }
-        (Err(_), Err(_)) => {}
-    };
+    }
 }
 
-fn sync_portal_cameras(
-    main_camera_query: Query<
-        &GlobalTransform,
-        (
-            With<FirstPersonCamera>,
-            Without<PortalCamera<0>>,
-            Without<PortalCamera<1>>,
-        ),
-    >,
-    portal_query_a: Query<
-        &GlobalTransform,
-        (
-            With<Portal<0>>,
-            Without<PortalCamera<0>>,
-            Without<PortalCamera<1>>,
-        ),
-    >,
-    portal_query_b: Query<
-        &GlobalTransform,
+/// Link every active portal to the other portal of its pair, if it exists and is active.
+fn link_portals(mut portal_query: Query<(&mut Portal, Entity)>) {
+    let mut pairs: HashMap<PortalLink, [Option<Entity>; 2]> = HashMap::new();
+    for (portal, entity) in &portal_query {
+        if portal.active {
+            pairs.entry(portal.link.clone()).or_default()[portal.end.index()] = Some(entity);
+        }
+    }
+    for (mut portal, _entity) in &mut portal_query {
+        let linked_portal = pairs
+            .get(&portal.link)
+            .filter(|_| portal.active)
+            .and_then(|pair| pair[portal.end.other().index()]);
+        if portal.linked_portal != linked_portal {
+            portal.linked_portal = linked_portal;
+        }
+    }
+}
+
+fn set_portal_materials(
+    mut commands: Commands,
+    portal_query: Query<&Portal>,
+    portal_meshes_query: Query<
         (
-            With<Portal<1>>,
-            Without<PortalCamera<0>>,
-            Without<PortalCamera<1>>,
+            Option<&PortalRecursionMesh>,
+            Option<&Parent>,
+            Option<&Handle<OpenPortalMaterial>>,
+            Option<&Handle<ClosedPortalMaterial>>,
+            Entity,
         ),
+        Or<(With<Portal>, With<PortalRecursionMesh>)>,
     >,
-    mut portal_cam_a_query: Query<
-        (&mut Transform, &mut PortalCameraProjection),
-        (With<PortalCamera<0>>, Without<PortalCamera<1>>),
-    >,
-    mut portal_cam_b_query: Query<
-        (&mut Transform, &mut PortalCameraProjection),
-        (With<PortalCamera<1>>, Without<PortalCamera<0>>),
+    resources: Res<PortalResources>,
+    recursion: Res<PortalRecursion>,
+) {
+    for (recursion_mesh, parent, open_material, closed_material, entity) in &portal_meshes_query {
+        // Portals are seen at depth 1 by the main camera.
+        let (portal, depth) = match (recursion_mesh, parent) {
+            (Some(recursion_mesh), Some(parent)) => match portal_query.get(**parent) {
+                Ok(portal) => (portal, recursion_mesh.depth),
+                Err(_) => continue,
+            },
+            (None, _) => match portal_query.get(entity) {
+                Ok(portal) => (portal, 1),
+                Err(_) => continue,
+            },
+            _ => continue,
+        };
+        if let Some(pair) = resources.pairs.get(&portal.link) {
+            let end = portal.end.index();
+            // The deepest recursion level shows the portals closed.
+            if portal.linked_portal.is_some() && depth <= recursion.depth() {
+                let material = &pair.open_materials[depth - 1][end];
+                if open_material != Some(material) {
+                    commands
+                        .entity(entity)
+                        .remove::<Handle<ClosedPortalMaterial>>()
+                        .insert(material.clone());
+                }
+            } else if closed_material != Some(&pair.closed_materials[end]) {
+                commands
+                    .entity(entity)
+                    .remove::<Handle<OpenPortalMaterial>>()
+                    .insert(pair.closed_materials[end].clone());
+            }
+        }
+    }
+}
+
+fn sync_portal_cameras(
+    main_camera_query: Query<&GlobalTransform, (With<FirstPersonCamera>, Without<PortalCamera>)>,
+    portal_query: Query<(&Portal, &GlobalTransform), Without<PortalCamera>>,
+    mut portal_cam_query: Query<
+        (&mut Transform, &mut PortalCameraProjection, &mut Camera),
+        With<PortalCamera>,
     >,
 ) {
-    if let (
-        Ok(trf_a),
-        Ok(trf_b),
-        Ok(trf_main_cam),
-        Ok((mut cam_a_trf, mut proj_a)),
-        Ok((mut cam_b_trf, mut proj_b)),
-    ) = (
-        portal_query_a.get_single(),
-        portal_query_b.get_single(),
-        main_camera_query.get_single(),
-        portal_cam_a_query.get_single_mut(),
-        portal_cam_b_query.get_single_mut(),
-    ) {
+    if let Ok(trf_main_cam) = main_camera_query.get_single() {
         let trf_main_cam = trf_main_cam.compute_transform();
-        let ta = trf_a.compute_transform();
-        let tb = trf_b.compute_transform();
-        *cam_a_trf = geometry::portal_to_portal(&ta, &tb) * trf_main_cam;
-        *cam_b_trf = geometry::portal_to_portal(&tb, &ta) * trf_main_cam;
+        for (portal, trf) in &portal_query {
+            let linked_trf = portal
+                .linked_portal
+                .and_then(|linked_portal| portal_query.get(linked_portal).ok());
+            let portal_to_linked = linked_trf.map(|(_, linked_trf)| {
+                geometry::portal_to_portal(
+                    &trf.compute_transform(),
+                    &linked_trf.compute_transform(),
+                )
+            });
+
+            // Every recursion level goes through the portal once more.
+            let mut recursion_trf = trf_main_cam;
+            for camera in &portal.cameras {
+                let (mut cam_trf, mut proj, mut cam) = match portal_cam_query.get_mut(*camera) {
+                    Ok(camera) => camera,
+                    Err(_) => continue,
+                };
+                // Closed portals show no view, don't render it.
+                if cam.is_active != linked_trf.is_some() {
+                    cam.is_active = linked_trf.is_some();
+                }
 
-        // Compute the clipping planes for both cameras.
-        // The plane normals are the rotated forward() direction of the portal transforms, and their origin
-        // is on the plane, which is enough to compute the plane homogeneous coords. They must be
-        // transformed to the camera reference frame afterwards.
-        let cam_a_clip_plane = PortalPlugin::get_portal_plane(trf_b);
-        let cam_b_clip_plane = PortalPlugin::get_portal_plane(trf_a);
+                if let (Some((_, linked_trf)), Some(portal_to_linked)) =
+                    (linked_trf, portal_to_linked)
+                {
+                    recursion_trf = portal_to_linked * recursion_trf;
+                    *cam_trf = recursion_trf;
 
-        // Inverse transpose of the view matrix = inverse inverse transpose of camera matrix = transpose
-        proj_a.near = cam_a_trf.compute_matrix().transpose() * cam_a_clip_plane;
-        proj_b.near = cam_b_trf.compute_matrix().transpose() * cam_b_clip_plane;
-        let d = proj_a.near.xyz().length_recip();
-        proj_a.near *= d;
-        let d = proj_b.near.xyz().length_recip();
-        proj_b.near *= d;
+                    // Compute the clipping plane of the camera.
+                    // The plane normal is the rotated forward() direction of the linked portal
+                    // transform, and its origin is on the plane, which is enough to compute the
+                    // plane homogeneous coords. It must be transformed to the camera reference
+                    // frame afterwards. Every recursion level is clipped by the same plane, as
+                    // the cameras all look through the linked portal.
+                    let clip_plane = PortalPlugin::get_portal_plane(linked_trf);
+
+                    // Inverse transpose of the view matrix = inverse inverse transpose of camera matrix = transpose
+                    proj.near = cam_trf.compute_matrix().transpose() * clip_plane;
+                    let d = proj.near.xyz().length_recip();
+                    proj.near *= d;
+                }
+            }
+        }
+    }
+}
+
+/// Give the portal cameras the field of view and far plane of the main camera.
+///
+/// Their aspect ratio follows the one of their render target, which matches the window.
+fn sync_portal_camera_projections(
+    main_camera_query: Query<&Projection, (With<FirstPersonCamera>, Changed<Projection>)>,
+    mut portal_cam_query: Query<&mut PortalCameraProjection, With<PortalCamera>>,
+) {
+    if let Ok(Projection::Perspective(main_projection)) = main_camera_query.get_single() {
+        for mut projection in &mut portal_cam_query {
+            projection.fov = main_projection.fov;
+            projection.far = main_projection.far;
+        }
     }
 }
 
 fn turn_off_collisions_with_static_geo_when_in_portal(
     mut collisions: EventReader<CollisionEvent>,
-    portal_a_query: Query<(Entity, &Portal<0>), Without<PortalTeleport>>,
-    portal_b_query: Query<(Entity, &Portal<1>), Without<PortalTeleport>>,
+    portal_query: Query<&Portal, Without<PortalTeleport>>,
     mut teleportable_query: Query<&mut CollisionGroups, With<PortalTeleport>>,
 ) {
-    if let (Ok((portal_a_entity, portal_a)), Ok((portal_b_entity, portal_b))) =
-        (portal_a_query.get_single(), portal_b_query.get_single())
-    {
-        for collision in collisions.iter() {
-            match collision {
-                CollisionEvent::Started(collider_a, collider_b, _flags) => {
-                    if collider_a == &portal_a_entity || collider_b == &portal_a_entity {
-                        let maybe_teleportable = if collider_a == &portal_a_entity {
-                            collider_b
-                        } else {
-                            collider_a
-                        };
-                        if let Ok(mut groups) = teleportable_query.get_mut(*maybe_teleportable) {
-                            groups.filters = portal_a.filter_collisions();
-                        }
-                    } else if collider_a == &portal_b_entity || collider_b == &portal_b_entity {
-                        let maybe_teleportable = if collider_a == &portal_b_entity {
-                            collider_b
-                        } else {
-                            collider_a
-                        };
-                        if let Ok(mut groups) = teleportable_query.get_mut(*maybe_teleportable) {
-                            groups.filters = portal_b.filter_collisions();
-                        }
-                    }
-                }
-                CollisionEvent::Stopped(collider_a, collider_b, _flags) => {
-                    if collider_a == &portal_a_entity || collider_b == &portal_a_entity {
-                        if let Ok(mut groups) = teleportable_query.get_mut(*collider_b) {
-                            groups.filters = portal_a.restore_collisions();
-                        }
-                    } else if collider_a == &portal_b_entity || collider_b == &portal_b_entity {
-                        if let Ok(mut groups) = teleportable_query.get_mut(*collider_a) {
-                            groups.filters = portal_b.restore_collisions();
-                        }
-                    }
-                }
+    for collision in collisions.iter() {
+        let (collider_a, collider_b, started) = match collision {
+            CollisionEvent::Started(collider_a, collider_b, _flags) => {
+                (collider_a, collider_b, true)
+            }
+            CollisionEvent::Stopped(collider_a, collider_b, _flags) => {
+                (collider_a, collider_b, false)
+            }
+        };
+        let (portal, maybe_teleportable) = if let Ok(portal) = portal_query.get(*collider_a) {
+            (portal, collider_b)
+        } else if let Ok(portal) = portal_query.get(*collider_b) {
+            (portal, collider_a)
+        } else {
+            continue;
+        };
+        if let Ok(mut groups) = teleportable_query.get_mut(*maybe_teleportable) {
+            if !started {
+                groups.filters = portal.restore_collisions();
+            } else if portal.linked_portal.is_some() {
+                // Only let objects through the surface when the portal leads somewhere
+                groups.filters = portal.filter_collisions();
             }
         }
     }
 }
 
+/// Return the transforms of every portal leading somewhere, along with the transform of its linked
+/// portal.
+fn linked_portal_transforms(
+    portal_query: &Query<(&Portal, &GlobalTransform), Without<PortalTeleport>>,
+) -> Vec<(Transform, Transform)> {
+    portal_query
+        .iter()
+        .filter_map(|(portal, trf)| {
+            let (_, linked_trf) = portal_query.get(portal.linked_portal?).ok()?;
+            Some((trf.compute_transform(), linked_trf.compute_transform()))
+        })
+        .collect()
+}
+
 fn teleport_props(
-    portal_a_query: Query<(&Transform, Entity), (With<Portal<0>>, Without<PortalTeleport>)>,
-    portal_b_query: Query<(&Transform, Entity), (With<Portal<1>>, Without<PortalTeleport>)>,
+    portal_query: Query<(&Portal, &GlobalTransform), Without<PortalTeleport>>,
     mut teleportables: Query<
-        (&mut Transform, &mut Velocity),
+        (&mut Transform, &mut Velocity, Option<&PortalExitPolicy>),
         (With<PortalTeleport>, Without<FirstPersonController>),
     >,
+    exit_policy: Res<PortalExitPolicy>,
 ) {
     const PROXIMITY_THRESHOLD: f32 = 1.0;
-    if let (Ok((portal_a_trf, _portal_a)), Ok((portal_b_trf, _portal_b))) =
-        (portal_a_query.get_single(), portal_b_query.get_single())
-    {
-        let mut a_to_b = None;
-        let mut b_to_a = None;
-        for (mut obj_transform, mut velocity) in &mut teleportables {
-            let a_clip_to_object = obj_transform.translation - portal_a_trf.translation
-                + portal_a_trf.forward() * PORTAL_MESH_DEPTH;
-            let b_clip_to_object = obj_transform.translation - portal_b_trf.translation
-                + portal_b_trf.forward() * PORTAL_MESH_DEPTH;
-            if a_clip_to_object.length() < PROXIMITY_THRESHOLD {
-                if a_clip_to_object.dot(portal_a_trf.forward()) > 0. {
-                    info!("Teleporting object from portal A to portal B");
-                    let transform = a_to_b.get_or_insert_with(|| {
-                        geometry::portal_to_portal(portal_a_trf, portal_b_trf)
-                    });
+    let portals = linked_portal_transforms(&portal_query);
+    if portals.is_empty() {
+        return;
+    }
+    for (mut obj_transform, mut velocity, policy) in &mut teleportables {
+        for (portal_trf, linked_trf) in &portals {
+            let clip_to_object = obj_transform.translation - portal_trf.translation
+                + portal_trf.forward() * PORTAL_MESH_DEPTH;
+            if clip_to_object.length() < PROXIMITY_THRESHOLD {
+                if clip_to_object.dot(portal_trf.forward()) > 0. {
+                    info!("Teleporting object to linked portal");
+                    let transform = geometry::portal_to_portal(portal_trf, linked_trf);
                     *obj_transform = transform.mul_transform(*obj_transform);
-                    velocity.linvel = transform.rotation.mul_vec3(velocity.linvel);
+                    velocity.linvel = policy.unwrap_or(&exit_policy).exit_velocity(
+                        &transform,
+                        linked_trf.back(),
+                        velocity.linvel,
+                    );
                     velocity.angvel = transform.rotation.mul_vec3(velocity.angvel);
                 }
-            } else if b_clip_to_object.length() < PROXIMITY_THRESHOLD
-                && b_clip_to_object.dot(portal_b_trf.forward()) > 0.
-            {
-                info!("Teleporting object from portal B to portal A");
-                let transform = b_to_a
-                    .get_or_insert_with(|| geometry::portal_to_portal(portal_b_trf, portal_a_trf));
-                *obj_transform = transform.mul_transform(*obj_transform);
-                velocity.linvel = transform.rotation.mul_vec3(velocity.linvel);
-                velocity.angvel = transform.rotation.mul_vec3(velocity.angvel);
+                break;
             }
         }
     }
@@ -774,48 +1388,40 @@ fn teleport_props(
 //   special care. If the computed transform is does not keep the player upright, then
 //   we introduce a short animation bringing the camera back in line with the physical model.
 fn teleport_player(
-    portal_a_query: Query<(&Transform, Entity), (With<Portal<0>>, Without<PortalTeleport>)>,
-    portal_b_query: Query<(&Transform, Entity), (With<Portal<1>>, Without<PortalTeleport>)>,
+    portal_query: Query<(&Portal, &GlobalTransform), Without<PortalTeleport>>,
     mut player: Query<
         (
             &mut Transform,
             &mut Velocity,
             &mut FirstPersonController,
+            Option<&PortalExitPolicy>,
             Entity,
         ),
         With<PortalTeleport>,
     >,
     mut camera_query: Query<
         (&mut Transform, &GlobalTransform),
-        (
-            With<CameraAnchor>,
-            Without<Portal<0>>,
-            Without<Portal<1>>,
-            Without<PortalTeleport>,
-        ),
+        (With<CameraAnchor>, Without<Portal>, Without<PortalTeleport>),
     >,
+    exit_policy: Res<PortalExitPolicy>,
 ) {
     // Player origin is on the ground, so offset the detection distance a bit
     const PLAYER_PROXIMITY_THRESHOLD: f32 = 2.3;
-    const MIN_OUTBOUND_SPEED: f32 = 3.;
-    if let (Ok((portal_a_trf, _portal_a)), Ok((portal_b_trf, _portal_b))) =
-        (portal_a_query.get_single(), portal_b_query.get_single())
+    let portals = linked_portal_transforms(&portal_query);
+    if let (
+        Ok((mut player_transform, mut velocity, mut player_controller, policy, player_entity)),
+        Ok((mut camera_transform, camera_global)),
+    ) = (player.get_single_mut(), camera_query.get_single_mut())
     {
-        if let (
-            Ok((mut player_transform, mut velocity, mut player_controller, player_entity)),
-            Ok((mut camera_transform, camera_global)),
-        ) = (player.get_single_mut(), camera_query.get_single_mut())
-        {
-            let a_clip_to_player = player_transform.translation - portal_a_trf.translation
-                + portal_a_trf.forward() * PORTAL_MESH_DEPTH;
-            let b_clip_to_player = player_transform.translation - portal_b_trf.translation
-                + portal_b_trf.forward() * PORTAL_MESH_DEPTH;
-            if a_clip_to_player.length() < PLAYER_PROXIMITY_THRESHOLD {
-                if a_clip_to_player.dot(portal_a_trf.forward()) > 0. {
-                    info!("Teleporting player from portal A to portal B");
-                    let a_to_b = geometry::portal_to_portal(portal_a_trf, portal_b_trf);
+        for (portal_trf, linked_trf) in &portals {
+            let clip_to_player = player_transform.translation - portal_trf.translation
+                + portal_trf.forward() * PORTAL_MESH_DEPTH;
+            if clip_to_player.length() < PLAYER_PROXIMITY_THRESHOLD {
+                if clip_to_player.dot(portal_trf.forward()) > 0. {
+                    info!("Teleporting player to linked portal");
+                    let portal_to_linked = geometry::portal_to_portal(portal_trf, linked_trf);
                     geometry::adjust_player_camera_on_teleport(
-                        &a_to_b,
+                        &portal_to_linked,
                         &camera_global.compute_transform(),
                         &mut camera_transform,
                         player_entity,
@@ -823,33 +1429,13 @@ fn teleport_player(
                         &mut player_controller,
                     );
 
-                    let output_direction = portal_b_trf.back();
-                    let transformed_velocity = a_to_b.rotation.mul_vec3(velocity.linvel);
-                    velocity.linvel = portal_b_trf.back() * transformed_velocity.length();
-                    if velocity.linvel.dot(output_direction) < MIN_OUTBOUND_SPEED {
-                        velocity.linvel += MIN_OUTBOUND_SPEED * output_direction;
-                    }
-                }
-            } else if b_clip_to_player.length() < PLAYER_PROXIMITY_THRESHOLD
-                && b_clip_to_player.dot(portal_b_trf.forward()) > 0.
-            {
-                info!("Teleporting player from portal B to portal A");
-                let b_to_a = geometry::portal_to_portal(portal_b_trf, portal_a_trf);
-                geometry::adjust_player_camera_on_teleport(
-                    &b_to_a,
-                    &camera_global.compute_transform(),
-                    &mut camera_transform,
-                    player_entity,
-                    &mut player_transform,
-                    &mut player_controller,
-                );
-
-                let output_direction = portal_a_trf.back();
-                let transformed_velocity = b_to_a.rotation.mul_vec3(velocity.linvel);
-                velocity.linvel = portal_a_trf.back() * transformed_velocity.length();
-                if velocity.linvel.dot(output_direction) < MIN_OUTBOUND_SPEED {
-                    velocity.linvel += MIN_OUTBOUND_SPEED * output_direction;
+                    velocity.linvel = policy.unwrap_or(&exit_policy).exit_velocity(
+                        &portal_to_linked,
+                        linked_trf.back(),
+                        velocity.linvel,
+                    );
                 }
+                break;
             }
         }
     }
@@ -884,14 +1470,25 @@ fn animate_camera_roll(
 const PORTAL_PHYSICS_DISTANCE: f32 = 3.;
 const PORTAL_BASE_PHYSICS_IMPULSE: f32 = 500.;
 
+/// Return the transform of the given end of the player portals, if it is open.
+fn find_player_portal<'a>(
+    portal_query: &'a Query<(&Portal, &GlobalTransform)>,
+    end: PortalEnd,
+) -> Option<&'a GlobalTransform> {
+    portal_query
+        .iter()
+        .find(|(portal, _)| portal.link == PortalLink::Player && portal.end == end)
+        .map(|(_, trf)| trf)
+}
+
 fn apply_portal_attraction(
     mut commands: Commands,
-    portal_a_query: Query<&GlobalTransform, (With<Portal<0>>, Without<Portal<1>>)>,
-    rigidbodies: Query<(&GlobalTransform, Entity), (With<RigidBody>, Without<Portal<0>>)>,
+    portal_query: Query<(&Portal, &GlobalTransform)>,
+    rigidbodies: Query<(&GlobalTransform, Entity), (With<RigidBody>, Without<Portal>)>,
     progress: Res<PlayerProgress>,
 ) {
-    if *progress == PlayerProgress::HasImprovedPortalGun {
-        if let Ok(portal_transform) = portal_a_query.get_single() {
+    if progress.has(Ability::Attract) {
+        if let Some(portal_transform) = find_player_portal(&portal_query, PortalEnd::A) {
             for (rb_transform, entity) in &rigidbodies {
                 let rb_to_portal = portal_transform.translation() - rb_transform.translation();
                 let distance = rb_to_portal.length();
@@ -911,12 +1508,12 @@ fn apply_portal_attraction(
 
 fn apply_portal_repulsion(
     mut commands: Commands,
-    portal_b_query: Query<&GlobalTransform, (With<Portal<1>>, Without<Portal<0>>)>,
-    rigidbodies: Query<(&GlobalTransform, Entity), (With<RigidBody>, Without<Portal<1>>)>,
+    portal_query: Query<(&Portal, &GlobalTransform)>,
+    rigidbodies: Query<(&GlobalTransform, Entity), (With<RigidBody>, Without<Portal>)>,
     progress: Res<PlayerProgress>,
 ) {
-    if *progress == PlayerProgress::HasImprovedPortalGun {
-        if let Ok(portal_transform) = portal_b_query.get_single() {
+    if progress.has(Ability::Repel) {
+        if let Some(portal_transform) = find_player_portal(&portal_query, PortalEnd::B) {
             for (rb_transform, entity) in &rigidbodies {
                 let portal_to_rb = rb_transform.translation() - portal_transform.translation();
                 let distance = portal_to_rb.length();
@@ -933,3 +1530,87 @@ fn apply_portal_repulsion(
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use std::f32::consts::PI;
+
+    use super::*;
+
+    /// Transform from a portal at the origin to a linked portal yawed by 180°.
+    fn yawed_pair() -> (Transform, Vec3) {
+        let portal = Transform::IDENTITY;
+        let linked = Transform::from_xyz(10., 0., 0.).with_rotation(Quat::from_rotation_y(PI));
+        (geometry::portal_to_portal(&portal, &linked), linked.back())
+    }
+
+    #[test]
+    fn recursion_layers_are_per_pair() {
+        for max_depth in [1, 2, 5] {
+            let layers = (0..3)
+                .flat_map(|pair| {
+                    (2..=max_depth + 1).map(move |depth| recursion_layer(pair, depth, max_depth))
+                })
+                .collect::<Vec<_>>();
+            let unique = layers.iter().collect::<HashSet<_>>();
+            assert_eq!(unique.len(), layers.len());
+            assert!(layers.iter().all(|layer| *layer >= 2));
+        }
+        let max_depth = MAX_PORTAL_RECURSION_DEPTH;
+        let layer = recursion_layer(7, max_depth + 1, max_depth);
+        assert!((layer as usize) < RenderLayers::TOTAL_LAYERS);
+    }
+
+    #[test]
+    fn preserve_rotated_rotates_velocity() {
+        let (transform, normal) = yawed_pair();
+        let velocity = Vec3::new(1., -2., -4.);
+        let exit = PortalExitPolicy::PreserveRotated.exit_velocity(&transform, normal, velocity);
+        assert!(exit.abs_diff_eq(transform.rotation * velocity, 1e-5));
+        assert!((exit.length() - velocity.length()).abs() < 1e-5);
+
+        let same_facing =
+            geometry::portal_to_portal(&Transform::IDENTITY, &Transform::from_xyz(10., 0., 0.));
+        let exit = PortalExitPolicy::PreserveRotated.exit_velocity(
+            &same_facing,
+            Vec3::Z,
+            Vec3::new(1., 0., -4.),
+        );
+        assert!(exit.abs_diff_eq(Vec3::new(-1., 0., 4.), 1e-5));
+    }
+
+    #[test]
+    fn force_normal_exits_along_normal() {
+        let (transform, normal) = yawed_pair();
+        let exit =
+            PortalExitPolicy::ForceNormal.exit_velocity(&transform, normal, Vec3::new(3., 0., -4.));
+        assert!(exit.abs_diff_eq(normal * 5., 1e-5));
+
+        // Slow entities are pushed out of the portal.
+        let exit = PortalExitPolicy::ForceNormal.exit_velocity(&transform, normal, Vec3::NEG_Z);
+        assert!(exit.abs_diff_eq(normal * (1. + MIN_OUTBOUND_SPEED), 1e-5));
+    }
+
+    #[test]
+    fn clamp_raises_slow_velocity() {
+        let (transform, normal) = yawed_pair();
+        let velocity = Vec3::new(0., 0., -1.);
+        let exit = PortalExitPolicy::Clamp(3., 10.).exit_velocity(&transform, normal, velocity);
+        assert!(exit.abs_diff_eq(transform.rotation * velocity * 3., 1e-5));
+
+        let exit = PortalExitPolicy::Clamp(3., 10.).exit_velocity(&transform, normal, Vec3::ZERO);
+        assert!(exit.abs_diff_eq(normal * 3., 1e-5));
+    }
+
+    #[test]
+    fn clamp_limits_fast_velocity() {
+        let (transform, normal) = yawed_pair();
+        let velocity = Vec3::new(0., -20., 0.);
+        let exit = PortalExitPolicy::Clamp(3., 10.).exit_velocity(&transform, normal, velocity);
+        assert!(exit.abs_diff_eq(transform.rotation * velocity / 2., 1e-5));
+
+        let velocity = Vec3::new(0., 0., -5.);
+        let exit = PortalExitPolicy::Clamp(3., 10.).exit_velocity(&transform, normal, velocity);
+        assert!(exit.abs_diff_eq(transform.rotation * velocity, 1e-5));
+    }
+}
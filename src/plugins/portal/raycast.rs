@@ -0,0 +1,142 @@
+//! Ray casts continuing through the open portals.
+
+use bevy::{ecs::system::SystemParam, prelude::*};
+use bevy_rapier3d::prelude::*;
+
+use super::{geometry, Portal, PortalPlugin};
+
+/// Maximum number of portals a ray goes through, in case it loops between two facing portals.
+const MAX_PORTAL_TRAVERSALS: usize = 8;
+
+/// Straight part of a ray going through portals.
+#[derive(Debug, Clone, Copy)]
+pub struct RaySegment {
+    pub origin: Vec3,
+    pub direction: Vec3,
+    pub length: f32,
+    /// Portal the segment goes through at its end, if any.
+    pub portal: Option<Entity>,
+}
+
+impl RaySegment {
+    pub fn end(&self) -> Vec3 {
+        self.origin + self.direction * self.length
+    }
+}
+
+/// Result of a ray cast through portals.
+#[derive(Debug, Clone, Default)]
+pub struct PortalRayHit {
+    /// Segments of the ray, the first one starting at the ray origin.
+    pub segments: Vec<RaySegment>,
+    /// Collider hit at the end of the last segment, with the intersection in world space.
+    pub hit: Option<(Entity, RayIntersection)>,
+}
+
+impl PortalRayHit {
+    /// Return the last segment of the ray, along which the hit collider was found.
+    pub fn last_segment(&self) -> Option<&RaySegment> {
+        self.segments.last()
+    }
+}
+
+/// Ray casts which go through the open portals to continue from their linked portal.
+#[derive(SystemParam)]
+pub struct PortalRaycast<'w, 's> {
+    pub rapier: Res<'w, RapierContext>,
+    portal_query: Query<'w, 's, (&'static Portal, &'static GlobalTransform, Entity)>,
+}
+
+impl<'w, 's> PortalRaycast<'w, 's> {
+    /// Cast a ray, going through the open portals it meets, up to a total distance of `max_toi`.
+    pub fn cast_ray(
+        &self,
+        origin: Vec3,
+        direction: Vec3,
+        max_toi: Real,
+        solid: bool,
+        filter: QueryFilter,
+    ) -> PortalRayHit {
+        let mut result = PortalRayHit::default();
+        let mut origin = origin;
+        let mut direction = direction.normalize();
+        let mut remaining = max_toi;
+        loop {
+            let portal = if result.segments.len() < MAX_PORTAL_TRAVERSALS {
+                self.find_portal_on_ray(origin, direction, remaining)
+            } else {
+                None
+            };
+            let hit = self.rapier.cast_ray_and_get_normal(
+                origin,
+                direction,
+                portal.map_or(remaining, |(_, toi, _)| toi),
+                solid,
+                filter,
+            );
+            match (hit, portal) {
+                (None, Some((entity, toi, portal_to_linked))) => {
+                    result.segments.push(RaySegment {
+                        origin,
+                        direction,
+                        length: toi,
+                        portal: Some(entity),
+                    });
+                    origin = portal_to_linked * (origin + direction * toi);
+                    direction = portal_to_linked.rotation * direction;
+                    remaining -= toi;
+                }
+                (hit, _) => {
+                    let length = hit.map_or(remaining, |(_, intersection)| intersection.toi);
+                    result.segments.push(RaySegment {
+                        origin,
+                        direction,
+                        length,
+                        portal: None,
+                    });
+                    result.hit = hit;
+                    return result;
+                }
+            }
+        }
+    }
+
+    /// Return the closest open portal entered by the ray, along with its distance and the transform
+    /// to its linked portal.
+    fn find_portal_on_ray(
+        &self,
+        origin: Vec3,
+        direction: Vec3,
+        max_toi: Real,
+    ) -> Option<(Entity, Real, Transform)> {
+        self.portal_query
+            .iter()
+            .filter_map(|(portal, trf, entity)| {
+                let (_, linked_trf, _) = self.portal_query.get(portal.linked_portal?).ok()?;
+                let plane = PortalPlugin::get_portal_plane(trf);
+                let normal = plane.truncate();
+                // Portals are only entered from the front.
+                let cos = direction.dot(normal);
+                if cos >= 0. {
+                    return None;
+                }
+                let toi = -(origin.dot(normal) + plane.w) / cos;
+                if toi < 0. || toi > max_toi {
+                    return None;
+                }
+                let offset = origin + direction * toi - trf.translation();
+                let (scale, _, _) = trf.to_scale_rotation_translation();
+                if offset.dot(trf.right()).abs() > scale.x / 2.
+                    || offset.dot(trf.up()).abs() > scale.y / 2.
+                {
+                    return None;
+                }
+                let portal_to_linked = geometry::portal_to_portal(
+                    &trf.compute_transform(),
+                    &linked_trf.compute_transform(),
+                );
+                Some((entity, toi, portal_to_linked))
+            })
+            .min_by(|(_, a, _), (_, b, _)| a.total_cmp(b))
+    }
+}
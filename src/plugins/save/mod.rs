@@ -0,0 +1,554 @@
+//! Saving and loading the game: the current level and section, the player, its progress, and the
+//! state of the doors, pickups and props of the level.
+//!
+//! Saves are JSON files holding a [`SaveGame`]. Every save records the version of its schema, and
+//! older saves are migrated to the current schema before being loaded.
+
+use std::{fmt, fs, io};
+
+use bevy::{ecs::system::SystemParam, prelude::*};
+use bevy_rapier3d::prelude::*;
+use iyes_loopless::prelude::*;
+use leafwing_input_manager::prelude::ActionState;
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+
+use super::{
+    asset_processor::{CarriedPlayer, CurrentLevel, Level, LevelProcessor},
+    doors::Door,
+    first_person_controller::FirstPersonController,
+    game::{
+        consume_pickup_sensor, restore_pickup_sensor, CollectedPickups, ConsumedPickupSensor,
+        GameState, Pickup, PickupSensor, PlayerProgress,
+    },
+    input::Actions,
+    physics::*,
+    portal::PortalTeleport,
+    signals::SignalSource,
+};
+
+pub struct SavePlugin;
+
+impl Plugin for SavePlugin {
+    fn build(&self, app: &mut App) {
+        app.add_system(quicksave.run_in_state(GameState::InGame))
+            .add_system(quickload.run_in_state(GameState::InGame))
+            .add_system(apply_pending_load.run_in_state(GameState::InGame));
+    }
+}
+
+/// File the quicksave is written to, in the working directory.
+pub const QUICKSAVE_FILE: &str = "quicksave.json";
+
+/// Migrations of the save schema, the migration at index `i` upgrades a save from version `i + 1`
+/// to version `i + 2`.
+///
+/// When the schema of [`SaveGame`] changes, add the migration from the previous version here.
+const MIGRATIONS: &[fn(&mut Value)] = &[migrate_progress_to_abilities];
+
+/// Version of the current save schema.
+pub const SAVE_VERSION: u32 = MIGRATIONS.len() as u32 + 1;
+
+/// Version 2 replaced the progress stages with the set of abilities unlocked by the player.
+fn migrate_progress_to_abilities(save: &mut Value) {
+    let abilities = match save.get("progress").and_then(Value::as_str) {
+        Some("HasPortalGun") => vec!["portal_a", "portal_b"],
+        Some("HasImprovedPortalGun") => vec!["portal_a", "portal_b", "attract", "repel"],
+        _ => Vec::new(),
+    };
+    if let Some(save) = save.as_object_mut() {
+        save.insert("progress".to_owned(), json!({ "abilities": abilities }));
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default, Serialize, Deserialize)]
+pub struct SavedTransform {
+    pub translation: [f32; 3],
+    pub rotation: [f32; 4],
+    pub scale: [f32; 3],
+}
+
+impl From<Transform> for SavedTransform {
+    fn from(transform: Transform) -> Self {
+        SavedTransform {
+            translation: transform.translation.to_array(),
+            rotation: transform.rotation.to_array(),
+            scale: transform.scale.to_array(),
+        }
+    }
+}
+
+impl From<SavedTransform> for Transform {
+    fn from(transform: SavedTransform) -> Self {
+        Transform {
+            translation: Vec3::from_array(transform.translation),
+            rotation: Quat::from_array(transform.rotation),
+            scale: Vec3::from_array(transform.scale),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default, Serialize, Deserialize)]
+pub struct SavedVelocity {
+    pub linvel: [f32; 3],
+    pub angvel: [f32; 3],
+}
+
+impl From<Velocity> for SavedVelocity {
+    fn from(velocity: Velocity) -> Self {
+        SavedVelocity {
+            linvel: velocity.linvel.to_array(),
+            angvel: velocity.angvel.to_array(),
+        }
+    }
+}
+
+impl From<SavedVelocity> for Velocity {
+    fn from(velocity: SavedVelocity) -> Self {
+        Velocity {
+            linvel: Vec3::from_array(velocity.linvel),
+            angvel: Vec3::from_array(velocity.angvel),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SavedDoor {
+    pub id: u32,
+    pub open: bool,
+}
+
+/// Level prop, identified by the name of its node.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SavedProp {
+    pub name: String,
+    pub transform: SavedTransform,
+    pub velocity: SavedVelocity,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SaveGame {
+    pub version: u32,
+    pub level: String,
+    pub section: String,
+    pub player_transform: SavedTransform,
+    pub player_velocity: SavedVelocity,
+    pub progress: PlayerProgress,
+    pub pickups: Vec<u32>,
+    pub doors: Vec<SavedDoor>,
+    pub props: Vec<SavedProp>,
+}
+
+#[derive(Debug)]
+pub enum SaveError {
+    Io(io::Error),
+    Json(serde_json::Error),
+    /// The save was written by a newer version of the game.
+    UnsupportedVersion(u32),
+    /// The level of the save has no section with the saved name.
+    UnknownSection {
+        level: String,
+        section: String,
+    },
+}
+
+impl fmt::Display for SaveError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SaveError::Io(e) => write!(f, "{}", e),
+            SaveError::Json(e) => write!(f, "invalid save: {}", e),
+            SaveError::UnsupportedVersion(version) => write!(
+                f,
+                "save version {} is newer than the supported version {}",
+                version, SAVE_VERSION
+            ),
+            SaveError::UnknownSection { level, section } => {
+                write!(f, "level {} has no section {}", level, section)
+            }
+        }
+    }
+}
+
+impl std::error::Error for SaveError {}
+
+impl From<io::Error> for SaveError {
+    fn from(e: io::Error) -> Self {
+        SaveError::Io(e)
+    }
+}
+
+impl From<serde_json::Error> for SaveError {
+    fn from(e: serde_json::Error) -> Self {
+        SaveError::Json(e)
+    }
+}
+
+impl SaveGame {
+    pub fn write(&self, path: &str) -> Result<(), SaveError> {
+        fs::write(path, serde_json::to_string_pretty(self)?)?;
+        Ok(())
+    }
+
+    /// Read a save, migrating it to the current schema.
+    pub fn read(path: &str) -> Result<SaveGame, SaveError> {
+        SaveGame::from_json(&fs::read_to_string(path)?)
+    }
+
+    /// Parse a save, migrating it to the current schema.
+    pub fn from_json(json: &str) -> Result<SaveGame, SaveError> {
+        let mut value = serde_json::from_str::<Value>(json)?;
+        let version = value
+            .get("version")
+            .and_then(Value::as_u64)
+            .unwrap_or_default() as u32;
+        if version > SAVE_VERSION {
+            return Err(SaveError::UnsupportedVersion(version));
+        }
+        for migration in MIGRATIONS.iter().skip(version.saturating_sub(1) as usize) {
+            migration(&mut value);
+        }
+        if let Some(save) = value.as_object_mut() {
+            save.insert("version".to_owned(), SAVE_VERSION.into());
+        }
+        Ok(serde_json::from_value(value)?)
+    }
+
+    /// Check that the saved section exists in the saved level, which must be loaded.
+    fn check_section(
+        &self,
+        level_manager: &LevelProcessor,
+        levels: &Assets<Level>,
+    ) -> Result<(), SaveError> {
+        if level_manager.level_has_section(&self.level, &self.section, levels) {
+            Ok(())
+        } else {
+            Err(SaveError::UnknownSection {
+                level: self.level.clone(),
+                section: self.section.clone(),
+            })
+        }
+    }
+}
+
+/// Save being loaded, once its level is spawned.
+#[derive(Debug, Resource)]
+struct PendingLoad {
+    save: SaveGame,
+    spawn_requested: bool,
+}
+
+/// The parts of the world recorded in the saves.
+#[derive(SystemParam)]
+struct SavedWorld<'w, 's> {
+    player_query: Query<
+        'w,
+        's,
+        (
+            &'static mut Transform,
+            &'static mut Velocity,
+            &'static mut FirstPersonController,
+        ),
+    >,
+    props_query: Query<
+        'w,
+        's,
+        (
+            &'static Name,
+            &'static mut Transform,
+            &'static GlobalTransform,
+            &'static mut Velocity,
+        ),
+        (With<PortalTeleport>, Without<FirstPersonController>),
+    >,
+    doors_query: Query<'w, 's, &'static mut Door>,
+    pickups_query: Query<'w, 's, (&'static Pickup, &'static mut Visibility)>,
+    pickup_sensors_query: Query<
+        'w,
+        's,
+        (
+            Option<&'static PickupSensor>,
+            Option<&'static ConsumedPickupSensor>,
+            Option<&'static mut SignalSource>,
+            Entity,
+        ),
+        Or<(With<PickupSensor>, With<ConsumedPickupSensor>)>,
+    >,
+    collected: ResMut<'w, CollectedPickups>,
+    progress: ResMut<'w, PlayerProgress>,
+}
+
+impl<'w, 's> SavedWorld<'w, 's> {
+    /// Record the state of the world, in the given level and section.
+    fn capture(&self, level: String, section: String) -> Option<SaveGame> {
+        let (player_transform, player_velocity, _) = self.player_query.get_single().ok()?;
+        let mut pickups = self.collected.ids.iter().copied().collect::<Vec<_>>();
+        pickups.sort();
+        let mut doors = self
+            .doors_query
+            .iter()
+            .map(|door| SavedDoor {
+                id: door.id,
+                open: door.open,
+            })
+            .collect::<Vec<_>>();
+        doors.sort_by_key(|door| door.id);
+        doors.dedup_by_key(|door| door.id);
+        // Thrown cubes have no name, and aren't saved.
+        let props = self
+            .props_query
+            .iter()
+            .map(|(name, _, global_transform, velocity)| SavedProp {
+                name: name.to_string(),
+                transform: global_transform.compute_transform().into(),
+                velocity: (*velocity).into(),
+            })
+            .collect();
+        Some(SaveGame {
+            version: SAVE_VERSION,
+            level,
+            section,
+            player_transform: (*player_transform).into(),
+            player_velocity: (*player_velocity).into(),
+            progress: self.progress.clone(),
+            pickups,
+            doors,
+            props,
+        })
+    }
+
+    /// Restore the state of the world, once the level of the save is spawned.
+    fn restore(&mut self, commands: &mut Commands, save: &SaveGame) {
+        if let Ok((mut transform, mut velocity, mut controller)) =
+            self.player_query.get_single_mut()
+        {
+            *transform = save.player_transform.into();
+            *velocity = save.player_velocity.into();
+            // Drop the carried prop, its saved transform is in world space.
+            if let Some(grabbed_object) = controller.grabbed_object.take() {
+                commands.entity(grabbed_object).remove_parent().insert((
+                    RigidBody::Dynamic,
+                    CollisionGroups::new(PROPS_GROUP, ALL_GROUPS),
+                ));
+            }
+        }
+
+        *self.progress = save.progress.clone();
+        self.collected.ids = save.pickups.iter().copied().collect();
+        for (pickup, mut visibility) in &mut self.pickups_query {
+            visibility.is_visible = !self.collected.ids.contains(&pickup.id);
+        }
+        for (sensor, consumed, source, entity) in &mut self.pickup_sensors_query {
+            match (sensor, consumed) {
+                (Some(sensor), _) if self.collected.ids.contains(&sensor.pickup_id) => {
+                    consume_pickup_sensor(commands, entity, sensor.clone(), source);
+                }
+                (_, Some(consumed)) if !self.collected.ids.contains(&consumed.sensor.pickup_id) => {
+                    restore_pickup_sensor(commands, entity, consumed.sensor.clone(), source);
+                }
+                _ => {}
+            }
+        }
+
+        for mut door in &mut self.doors_query {
+            if let Some(saved) = save.doors.iter().find(|saved| saved.id == door.id) {
+                if door.open != saved.open {
+                    door.open = saved.open;
+                }
+            }
+        }
+
+        for (name, mut transform, _, mut velocity) in &mut self.props_query {
+            if let Some(saved) = save.props.iter().find(|saved| saved.name == name.as_str()) {
+                *transform = saved.transform.into();
+                *velocity = saved.velocity.into();
+            }
+        }
+    }
+}
+
+fn quicksave(
+    input_query: Query<&ActionState<Actions>, With<FirstPersonController>>,
+    world: SavedWorld,
+    current_level: Res<CurrentLevel>,
+    levels: Res<Assets<Level>>,
+) {
+    if !input_query
+        .get_single()
+        .map_or(false, |input| input.just_pressed(Actions::QuickSave))
+    {
+        return;
+    }
+    let level = match levels.get(&current_level.get()) {
+        Some(level) => level.name.clone(),
+        None => return,
+    };
+    if let Some(save) = world.capture(level, current_level.current_section()) {
+        match save.write(QUICKSAVE_FILE) {
+            Ok(()) => info!("Game saved to {}", QUICKSAVE_FILE),
+            Err(e) => error!("Can not save the game: {}", e),
+        }
+    }
+}
+
+fn quickload(
+    mut commands: Commands,
+    input_query: Query<&ActionState<Actions>, With<FirstPersonController>>,
+) {
+    if !input_query
+        .get_single()
+        .map_or(false, |input| input.just_pressed(Actions::QuickLoad))
+    {
+        return;
+    }
+    match SaveGame::read(QUICKSAVE_FILE) {
+        Ok(save) => {
+            info!("Loading game from {}", QUICKSAVE_FILE);
+            commands.insert_resource(PendingLoad {
+                save,
+                spawn_requested: false,
+            });
+        }
+        Err(e) => error!("Can not load the game: {}", e),
+    }
+}
+
+/// Spawn the level of the save being loaded if it isn't the current one, then restore the save.
+fn apply_pending_load(
+    mut commands: Commands,
+    mut world: SavedWorld,
+    mut level_manager: ResMut<LevelProcessor>,
+    current_level: Option<ResMut<CurrentLevel>>,
+    levels: Res<Assets<Level>>,
+    asset_server: Res<AssetServer>,
+    pending: Option<ResMut<PendingLoad>>,
+) {
+    let (mut pending, mut current_level) = match (pending, current_level) {
+        (Some(pending), Some(current_level)) => (pending, current_level),
+        _ => return,
+    };
+    let level = pending.save.level.clone();
+    if !level_manager.is_level_loaded(&level) {
+        if let Err(e) = level_manager.preload_level(&level, &asset_server) {
+            error!("Can not load level {}: {}", level, e);
+            commands.remove_resource::<PendingLoad>();
+        }
+        return;
+    }
+    if let Err(e) = pending.save.check_section(&level_manager, &levels) {
+        error!("Can not load the game: {}", e);
+        commands.remove_resource::<PendingLoad>();
+        return;
+    }
+
+    let in_level = levels
+        .get(&current_level.get())
+        .map_or(false, |level| level.name == pending.save.level);
+    if in_level {
+        world.restore(&mut commands, &pending.save);
+        current_level.section = pending.save.section.clone();
+        commands.remove_resource::<PendingLoad>();
+        return;
+    }
+    if pending.spawn_requested {
+        return;
+    }
+
+    // The player is moved to its saved transform once the level is spawned.
+    let carried = CarriedPlayer {
+        section: pending.save.section.clone(),
+        transform: Transform::IDENTITY,
+        velocity: Velocity::default(),
+    };
+    if let Ok((_, _, mut controller)) = world.player_query.get_single_mut() {
+        // The props of the current level are about to be despawned
+        controller.grabbed_object = None;
+    }
+    match level_manager.carry_player_to_level(&mut commands, &levels, &level, carried) {
+        Ok(()) => pending.spawn_requested = true,
+        Err(e) => {
+            error!("Can not load level {}: {}", level, e);
+            commands.remove_resource::<PendingLoad>();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use bevy::utils::HashSet;
+
+    use crate::plugins::game::Ability;
+
+    use super::*;
+
+    /// Save written before the saves had a version.
+    fn v1_save(progress: &str) -> Value {
+        json!({
+            "level": "Level1",
+            "section": "Section1",
+            "player_transform": SavedTransform::from(Transform::IDENTITY),
+            "player_velocity": SavedVelocity::default(),
+            "progress": progress,
+            "pickups": [1],
+            "doors": [{ "id": 2, "open": true }],
+            "props": [],
+        })
+    }
+
+    #[test]
+    fn unversioned_save_migrates_from_v1() {
+        let save = SaveGame::from_json(&v1_save("HasPortalGun").to_string()).unwrap();
+        assert_eq!(save.version, SAVE_VERSION);
+        assert_eq!(save.level, "Level1");
+        assert_eq!(
+            save.progress.abilities,
+            [Ability::PortalA, Ability::PortalB]
+                .into_iter()
+                .collect::<HashSet<_>>()
+        );
+    }
+
+    #[test]
+    fn improved_portal_gun_grants_every_ability() {
+        let mut save = v1_save("HasImprovedPortalGun");
+        save["version"] = 1.into();
+        let save = SaveGame::from_json(&save.to_string()).unwrap();
+        assert_eq!(
+            save.progress.abilities,
+            [
+                Ability::PortalA,
+                Ability::PortalB,
+                Ability::Attract,
+                Ability::Repel,
+            ]
+            .into_iter()
+            .collect::<HashSet<_>>()
+        );
+    }
+
+    #[test]
+    fn no_progress_grants_no_ability() {
+        let save = SaveGame::from_json(&v1_save("None").to_string()).unwrap();
+        assert!(save.progress.abilities.is_empty());
+    }
+
+    #[test]
+    fn current_save_is_not_migrated() {
+        let mut progress = PlayerProgress::default();
+        progress.abilities.insert(Ability::Attract);
+        let mut save = v1_save("");
+        save["version"] = SAVE_VERSION.into();
+        save["progress"] = serde_json::to_value(&progress).unwrap();
+        let save = SaveGame::from_json(&save.to_string()).unwrap();
+        assert_eq!(save.progress, progress);
+    }
+
+    #[test]
+    fn future_save_is_unsupported() {
+        let mut save = v1_save("HasPortalGun");
+        save["version"] = (SAVE_VERSION + 1).into();
+        match SaveGame::from_json(&save.to_string()) {
+            Err(SaveError::UnsupportedVersion(version)) => assert_eq!(version, SAVE_VERSION + 1),
+            other => panic!("Unexpected result {:?}", other),
+        }
+    }
+}
@@ -0,0 +1,314 @@
+//! Named boolean signals linking the puzzle elements of a level.
+//!
+//! Sources drive a signal: sensor volumes, door triggers, laser receivers, floor buttons, pickup
+//! sensors and logic gates combining other signals. Sinks follow a signal: doors open while it is
+//! high, level portals are active while it is high, and section transitions only trigger while it
+//! is high.
+//!
+//! Everything is authored with the `signal`, `logic`, `inputs`, `duration` and `on_signal` extras,
+//! see [`NodeExtras`](super::asset_processor::NodeExtras).
+
+use bevy::{
+    prelude::*,
+    reflect::FromReflect,
+    utils::{HashMap, HashSet},
+};
+use bevy_rapier3d::prelude::*;
+use serde::Deserialize;
+
+use super::{
+    buttons::FloorButton,
+    doors::{Door, DoorLabels, DoorSensor},
+    lasers::LaserReceiver,
+    portal::{LevelPortal, Portal},
+};
+
+pub struct SignalsPlugin;
+
+impl Plugin for SignalsPlugin {
+    fn build(&self, app: &mut App) {
+        app.register_type::<SignalSource>()
+            .register_type::<SignalSensor>()
+            .register_type::<SignalSink>()
+            .register_type::<LogicGate>()
+            .register_type::<LogicOp>()
+            .init_resource::<Signals>()
+            .add_event::<SignalChanged>()
+            .add_system_set(
+                SystemSet::new()
+                    .label(SignalLabels::UpdateSources)
+                    .with_system(update_sensor_signals)
+                    .with_system(update_door_sensor_signals)
+                    .with_system(update_laser_receiver_signals)
+                    .with_system(update_floor_button_signals),
+            )
+            .add_system(
+                evaluate_signals
+                    .label(SignalLabels::Evaluate)
+                    .after(SignalLabels::UpdateSources),
+            )
+            .add_system_set(
+                SystemSet::new()
+                    .after(SignalLabels::Evaluate)
+                    .before(DoorLabels::Animate)
+                    .with_system(open_doors_on_signal)
+                    .with_system(unlock_doors_on_signal)
+                    .with_system(activate_portals_on_signal),
+            );
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash, SystemLabel)]
+pub enum SignalLabels {
+    UpdateSources,
+    Evaluate,
+}
+
+/// Current value of every signal driven by a source of the level.
+#[derive(Debug, Default, Resource)]
+pub struct Signals {
+    values: HashMap<String, bool>,
+}
+
+impl Signals {
+    /// Whether the signal is high, signals without any source are low.
+    pub fn is_high(&self, signal: &str) -> bool {
+        self.values.get(signal).copied().unwrap_or(false)
+    }
+}
+
+/// Sent when a signal goes high or low.
+#[derive(Debug, Clone)]
+pub struct SignalChanged {
+    pub signal: String,
+    pub high: bool,
+}
+
+/// Node driving a signal, the signal is high if any of its sources is high.
+#[derive(Debug, Default, Component, Reflect, FromReflect)]
+#[reflect(Component)]
+pub struct SignalSource {
+    pub signal: String,
+    pub high: bool,
+}
+
+/// Sensor volume whose signal is high while something is inside it.
+#[derive(Debug, Default, Component, Reflect, FromReflect)]
+#[reflect(Component)]
+pub struct SignalSensor {
+    pub active_collisions: HashSet<Entity>,
+}
+
+/// Node following a signal: a door, a level portal or a section transition.
+#[derive(Debug, Default, Component, Reflect, FromReflect)]
+#[reflect(Component)]
+pub struct SignalSink {
+    pub signal: String,
+}
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Deserialize, Reflect, FromReflect)]
+#[serde(rename_all = "snake_case")]
+pub enum LogicOp {
+    /// High while all the inputs are high.
+    #[default]
+    And,
+    /// High while any input is high.
+    Or,
+    /// High while no input is high.
+    Not,
+    /// High while any input is high, and for `duration` seconds after.
+    Timer,
+    /// Set high by the first input, and back low by the second one.
+    Latch,
+}
+
+/// Logic node driving its signal from the signals of its inputs.
+#[derive(Debug, Default, Component, Reflect, FromReflect)]
+#[reflect(Component)]
+pub struct LogicGate {
+    pub op: LogicOp,
+    pub inputs: Vec<String>,
+    /// Duration of a [`LogicOp::Timer`], in seconds.
+    pub duration: f32,
+    /// Time left before a [`LogicOp::Timer`] goes low.
+    pub remaining: f32,
+}
+
+impl LogicGate {
+    /// Compute the output of the gate, given its previous output and the value of its inputs.
+    fn evaluate(&mut self, high: bool, inputs: &[bool], delta: f32) -> bool {
+        match self.op {
+            LogicOp::And => !inputs.is_empty() && inputs.iter().all(|input| *input),
+            LogicOp::Or => inputs.iter().any(|input| *input),
+            LogicOp::Not => !inputs.iter().any(|input| *input),
+            LogicOp::Timer => {
+                if inputs.iter().any(|input| *input) {
+                    self.remaining = self.duration;
+                    true
+                } else {
+                    self.remaining = (self.remaining - delta).max(0.);
+                    self.remaining > 0.
+                }
+            }
+            LogicOp::Latch => {
+                let set = inputs.first().copied().unwrap_or(false);
+                let reset = inputs.get(1).copied().unwrap_or(false);
+                if reset {
+                    false
+                } else {
+                    high || set
+                }
+            }
+        }
+    }
+}
+
+fn update_sensor_signals(
+    mut collisions: EventReader<CollisionEvent>,
+    mut sensor_query: Query<(&mut SignalSensor, &mut SignalSource)>,
+) {
+    for collision in collisions.iter() {
+        let (collider_a, collider_b, started) = match collision {
+            CollisionEvent::Started(collider_a, collider_b, _flags) => {
+                (collider_a, collider_b, true)
+            }
+            CollisionEvent::Stopped(collider_a, collider_b, _flags) => {
+                (collider_a, collider_b, false)
+            }
+        };
+        let (sensor_entity, cause) = if sensor_query.contains(*collider_a) {
+            (*collider_a, *collider_b)
+        } else if sensor_query.contains(*collider_b) {
+            (*collider_b, *collider_a)
+        } else {
+            continue;
+        };
+        let (mut sensor, mut source) = sensor_query.get_mut(sensor_entity).unwrap();
+        if started {
+            sensor.active_collisions.insert(cause);
+        } else {
+            sensor.active_collisions.remove(&cause);
+        }
+        source.high = !sensor.active_collisions.is_empty();
+    }
+}
+
+fn update_door_sensor_signals(
+    mut sensor_query: Query<(&DoorSensor, &mut SignalSource), Changed<DoorSensor>>,
+) {
+    for (sensor, mut source) in &mut sensor_query {
+        source.high = !sensor.active_collisions.is_empty();
+    }
+}
+
+fn update_laser_receiver_signals(
+    mut receiver_query: Query<(&LaserReceiver, &mut SignalSource), Changed<LaserReceiver>>,
+) {
+    for (receiver, mut source) in &mut receiver_query {
+        source.high = receiver.lit;
+    }
+}
+
+fn update_floor_button_signals(
+    mut button_query: Query<(&FloorButton, &mut SignalSource), Changed<FloorButton>>,
+) {
+    for (button, mut source) in &mut button_query {
+        source.high = button.pressed;
+    }
+}
+
+/// Compute the value of every signal from its sources, and send the changes.
+///
+/// Logic gates read the outputs of the gates evaluated before them in the same frame, and the
+/// previous frame outputs of the others, so a signal may take a few frames to go through a chain
+/// of gates.
+fn evaluate_signals(
+    mut signals: ResMut<Signals>,
+    mut sources_query: Query<(&mut SignalSource, Option<&mut LogicGate>)>,
+    mut changes: EventWriter<SignalChanged>,
+    time: Res<Time>,
+) {
+    let mut values = HashMap::new();
+    for (source, gate) in &sources_query {
+        if gate.is_none() {
+            *values.entry(source.signal.clone()).or_insert(false) |= source.high;
+        }
+    }
+
+    let delta = time.delta_seconds();
+    for (mut source, gate) in &mut sources_query {
+        if let Some(mut gate) = gate {
+            let inputs = gate
+                .inputs
+                .iter()
+                .map(|input| {
+                    values
+                        .get(input)
+                        .copied()
+                        .unwrap_or_else(|| signals.is_high(input))
+                })
+                .collect::<Vec<_>>();
+            let high = gate.evaluate(source.high, &inputs, delta);
+            if source.high != high {
+                source.high = high;
+            }
+            *values.entry(source.signal.clone()).or_insert(false) |= high;
+        }
+    }
+
+    for (signal, high) in &values {
+        if signals.is_high(signal) != *high {
+            info!("Signal {} {}", signal, if *high { "high" } else { "low" });
+            changes.send(SignalChanged {
+                signal: signal.clone(),
+                high: *high,
+            });
+        }
+    }
+    if signals.values != values {
+        signals.values = values;
+    }
+}
+
+fn open_doors_on_signal(
+    mut doors_query: Query<(&SignalSink, &mut Door)>,
+    mut changes: EventReader<SignalChanged>,
+) {
+    for change in changes.iter() {
+        for (sink, mut door) in &mut doors_query {
+            if sink.signal == change.signal && door.open != change.high {
+                door.open = change.high;
+            }
+        }
+    }
+}
+
+/// Unlock the locked doors while their signal is high.
+fn unlock_doors_on_signal(signals: Res<Signals>, mut doors_query: Query<&mut Door>) {
+    for mut door in &mut doors_query {
+        if let Some(signal) = &door.unlock_signal {
+            let locked = !signals.is_high(signal);
+            if door.locked != locked {
+                door.locked = locked;
+            }
+        }
+    }
+}
+
+/// Open the level portals while their signal is high.
+fn activate_portals_on_signal(
+    signals: Res<Signals>,
+    sinks_query: Query<(&SignalSink, &Children), With<LevelPortal>>,
+    mut portal_query: Query<&mut Portal>,
+) {
+    for (sink, children) in &sinks_query {
+        let high = signals.is_high(&sink.signal);
+        for child in children {
+            if let Ok(mut portal) = portal_query.get_mut(*child) {
+                if portal.active != high {
+                    portal.active = high;
+                }
+            }
+        }
+    }
+}